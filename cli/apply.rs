@@ -0,0 +1,399 @@
+use super::Result;
+use helium_console::{
+    AppEui, AppKey, ConsoleApi, ConsoleError, DetailedDevice, DevEui, DeviceIndex, DeviceLabel,
+    DeviceUpdate, NewDevice, NewLabel,
+};
+use serde_derive::Deserialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt, fs,
+    path::Path,
+};
+
+/// Desired state of an organization, as written in a fleet manifest:
+///
+/// ```yaml
+/// labels:
+///   - warehouse
+/// devices:
+///   - name: sensor-1
+///     dev_eui: 0004A30B001C0530
+///     app_eui: 70B3D57ED0001234
+///     app_key: 0123456789ABCDEF0123456789ABCDEF
+///     labels: [warehouse]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    devices: Vec<ManifestDevice>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestDevice {
+    name: String,
+    dev_eui: DevEui,
+    app_eui: AppEui,
+    app_key: AppKey,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let contents = fs::read_to_string(path)?;
+        let manifest: Manifest = serde_yaml::from_str(&contents)?;
+
+        let mut seen = BTreeSet::new();
+        for device in &manifest.devices {
+            if !seen.insert((device.dev_eui, device.app_eui)) {
+                return Err(format!(
+                    "device with dev_eui {} and app_eui {} is listed twice",
+                    device.dev_eui, device.app_eui
+                )
+                .into());
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Every label the manifest mentions, including those only used by devices
+    fn label_names(&self) -> BTreeSet<String> {
+        let mut names: BTreeSet<String> = self.labels.iter().cloned().collect();
+        for device in &self.devices {
+            names.extend(device.labels.iter().cloned());
+        }
+        names
+    }
+}
+
+/// One step of a plan
+#[derive(Debug)]
+pub enum Change {
+    CreateLabel {
+        name: String,
+    },
+    CreateDevice {
+        device: NewDevice,
+        labels: Vec<String>,
+    },
+    UpdateDevice {
+        id: String,
+        description: String,
+        rename: Option<(String, String)>,
+        add_labels: Vec<String>,
+        /// (name, id) of each label to detach
+        remove_labels: Vec<(String, String)>,
+    },
+    DeleteDevice {
+        id: String,
+        description: String,
+    },
+    DeleteLabel {
+        id: String,
+        name: String,
+    },
+}
+
+fn describe(name: &str, dev_eui: &DevEui, app_eui: &AppEui) -> String {
+    format!("\"{}\" (dev_eui {}, app_eui {})", name, dev_eui, app_eui)
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::CreateLabel { name } => write!(f, "  + label \"{}\"", name),
+            Change::CreateDevice { device, labels } => {
+                write!(
+                    f,
+                    "  + device {}",
+                    describe(device.name(), device.dev_eui(), device.app_eui())
+                )?;
+                for label in labels {
+                    write!(f, "\n      + label {}", label)?;
+                }
+                Ok(())
+            }
+            Change::UpdateDevice {
+                description,
+                rename,
+                add_labels,
+                remove_labels,
+                ..
+            } => {
+                write!(f, "  ~ device {}", description)?;
+                if let Some((from, to)) = rename {
+                    write!(f, "\n      name: \"{}\" -> \"{}\"", from, to)?;
+                }
+                for label in add_labels {
+                    write!(f, "\n      + label {}", label)?;
+                }
+                for (label, _) in remove_labels {
+                    write!(f, "\n      - label {}", label)?;
+                }
+                Ok(())
+            }
+            Change::DeleteDevice { description, .. } => write!(f, "  - device {}", description),
+            Change::DeleteLabel { name, .. } => write!(f, "  - label \"{}\"", name),
+        }
+    }
+}
+
+/// Changes needed to bring the organization in line with a manifest.
+/// Devices are matched on (dev_eui, app_eui) and labels by name under the
+/// client's `LabelMatch` policy. The Console cannot change a device's
+/// AppKey, so a manifest AppKey that differs from the live one is an error.
+#[derive(Debug, Default)]
+pub struct Plan {
+    changes: Vec<Change>,
+}
+
+impl Plan {
+    pub async fn compute<C: ConsoleApi>(
+        client: &mut C,
+        manifest: &Manifest,
+        prune: bool,
+    ) -> Result<Plan> {
+        let labels = client.get_labels().await?;
+        let devices = client.get_detailed_devices().await?;
+
+        let label_names: HashMap<&String, &String> = labels
+            .iter()
+            .map(|label| (label.id(), label.name()))
+            .collect();
+        let name_of = |id: &String| {
+            label_names
+                .get(id)
+                .map(|name| (*name).clone())
+                .unwrap_or_else(|| id.clone())
+        };
+        let existing: HashMap<(DevEui, AppEui), &DetailedDevice> = devices
+            .iter()
+            .map(|device| ((*device.dev_eui(), *device.app_eui()), device))
+            .collect();
+
+        // id of the live label each manifest label refers to, if it exists
+        let mut changes = Vec::new();
+        let mut label_ids: HashMap<String, Option<String>> = HashMap::new();
+        for name in manifest.label_names() {
+            let id = match client.get_label_uuid(&name, false).await {
+                Ok(id) => Some(id),
+                Err(ConsoleError::UnknownLabel(_)) => {
+                    changes.push(Change::CreateLabel { name: name.clone() });
+                    None
+                }
+                Err(err) => return Err(err.into()),
+            };
+            label_ids.insert(name, id);
+        }
+
+        let mut rekeyed = Vec::new();
+        for wanted in &manifest.devices {
+            let device = match existing.get(&(wanted.dev_eui, wanted.app_eui)) {
+                Some(device) => device,
+                None => {
+                    changes.push(Change::CreateDevice {
+                        device: NewDevice::new(
+                            wanted.app_eui,
+                            wanted.app_key,
+                            wanted.dev_eui,
+                            wanted.name.clone(),
+                        ),
+                        labels: wanted.labels.clone(),
+                    });
+                    continue;
+                }
+            };
+            if device.app_key().expose_secret() != &wanted.app_key {
+                rekeyed.push(describe(device.name(), device.dev_eui(), device.app_eui()));
+                continue;
+            }
+
+            let desired: BTreeSet<&String> = wanted
+                .labels
+                .iter()
+                .filter_map(|name| label_ids[name].as_ref())
+                .collect();
+            let rename = if device.name() != &wanted.name {
+                Some((device.name().clone(), wanted.name.clone()))
+            } else {
+                None
+            };
+            let add_labels: Vec<String> = wanted
+                .labels
+                .iter()
+                .filter(|name| match &label_ids[*name] {
+                    Some(id) => !device.labels().contains(id),
+                    None => true,
+                })
+                .cloned()
+                .collect();
+            let remove_labels: Vec<(String, String)> = device
+                .labels()
+                .iter()
+                .filter(|id| !desired.contains(id))
+                .map(|id| (name_of(id), id.clone()))
+                .collect();
+            if rename.is_some() || !add_labels.is_empty() || !remove_labels.is_empty() {
+                changes.push(Change::UpdateDevice {
+                    id: device.id().clone(),
+                    description: describe(device.name(), device.dev_eui(), device.app_eui()),
+                    rename,
+                    add_labels,
+                    remove_labels,
+                });
+            }
+        }
+        if !rekeyed.is_empty() {
+            return Err(format!(
+                "The manifest has a different app_key for {}. The Console cannot change a \
+                 device's AppKey: delete the device and apply again to re-create it",
+                rekeyed.join(", ")
+            )
+            .into());
+        }
+
+        if prune {
+            let listed: BTreeSet<(DevEui, AppEui)> = manifest
+                .devices
+                .iter()
+                .map(|device| (device.dev_eui, device.app_eui))
+                .collect();
+            for device in &devices {
+                if !listed.contains(&(*device.dev_eui(), *device.app_eui())) {
+                    changes.push(Change::DeleteDevice {
+                        id: device.id().clone(),
+                        description: describe(device.name(), device.dev_eui(), device.app_eui()),
+                    });
+                }
+            }
+            let wanted_labels: BTreeSet<&String> = label_ids.values().flatten().collect();
+            for label in &labels {
+                if !wanted_labels.contains(label.id()) {
+                    changes.push(Change::DeleteLabel {
+                        id: label.id().clone(),
+                        name: label.name().clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(Plan { changes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Prints the plan in the style of `terraform plan`
+    pub fn print(&self) {
+        if self.is_empty() {
+            println!("No changes. The organization matches the manifest.");
+            return;
+        }
+        println!("The following changes will be made:\n");
+        let (mut create, mut change, mut delete) = (0, 0, 0);
+        for step in &self.changes {
+            println!("{}", step);
+            match step {
+                Change::CreateLabel { .. } | Change::CreateDevice { .. } => create += 1,
+                Change::UpdateDevice { .. } => change += 1,
+                Change::DeleteDevice { .. } | Change::DeleteLabel { .. } => delete += 1,
+            }
+        }
+        println!(
+            "\nPlan: {} to create, {} to change, {} to delete.",
+            create, change, delete
+        );
+    }
+
+    /// Runs every change in order: labels first so devices can use them,
+    /// deletions last
+    pub async fn execute<C: ConsoleApi>(self, client: &mut C) -> Result {
+        // labels created by this plan, which a dry run never stores
+        let mut created_labels = HashMap::new();
+        let existing = DeviceIndex::new();
+        for step in self.changes {
+            match step {
+                Change::CreateLabel { name } => {
+                    let label = client.post_label(&NewLabel::from_string(&name)).await?;
+                    println!("Created label \"{}\"", name);
+                    created_labels.insert(name, label.id().clone());
+                }
+                Change::CreateDevice { device, labels } => {
+                    let created = match client.post_device(&device).await {
+                        Ok(created) => created,
+                        Err(ConsoleError::Conflict(_)) => {
+                            existing.get_device(client, &(&device).into()).await?
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+                    for label in &labels {
+                        let device_label = label_uuid(client, &created_labels, label).await?;
+                        client
+                            .add_device_label(created.id().clone(), &device_label)
+                            .await?;
+                    }
+                    println!("Created device \"{}\"", device.name());
+                }
+                Change::UpdateDevice {
+                    id,
+                    description,
+                    rename,
+                    add_labels,
+                    remove_labels,
+                } => {
+                    if let Some((_, name)) = rename {
+                        client
+                            .update_device(&id, &DeviceUpdate::new().name(name))
+                            .await?;
+                    }
+                    for label in &add_labels {
+                        let device_label = label_uuid(client, &created_labels, label).await?;
+                        client.add_device_label(id.clone(), &device_label).await?;
+                    }
+                    for (_, label_id) in remove_labels {
+                        let device_label = DeviceLabel::from_uuid(label_id)?;
+                        match client.remove_device_label(id.clone(), &device_label).await {
+                            Ok(()) | Err(ConsoleError::NotFound(_)) => (),
+                            Err(err) => return Err(err.into()),
+                        }
+                    }
+                    println!("Updated device {}", description);
+                }
+                Change::DeleteDevice { id, description } => {
+                    match client.delete_device(&id).await {
+                        Ok(()) | Err(ConsoleError::NotFound(_)) => (),
+                        Err(err) => return Err(err.into()),
+                    }
+                    println!("Deleted device {}", description);
+                }
+                Change::DeleteLabel { id, name } => {
+                    match client.delete_label(&id).await {
+                        Ok(()) | Err(ConsoleError::NotFound(_)) => (),
+                        Err(err) => return Err(err.into()),
+                    }
+                    println!("Deleted label \"{}\"", name);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Manifest labels are resolved again when applied, since the plan may
+/// have created them
+async fn label_uuid<C: ConsoleApi>(
+    client: &mut C,
+    created_labels: &HashMap<String, String>,
+    label: &str,
+) -> Result<DeviceLabel> {
+    let id = match created_labels.get(label) {
+        Some(id) => id.clone(),
+        None => client.get_label_uuid(label, false).await?,
+    };
+    Ok(DeviceLabel::from_uuid(id)?)
+}
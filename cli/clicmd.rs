@@ -1,86 +1,382 @@
+use super::{export, filter::DeviceFilter, import};
+use helium_console::{AppEui, AppKey, DevEui};
+use std::{path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 pub enum DeviceCmd {
     /// List all your organization's devices
     List {
+        /// JSON on a single line; shorthand kept from before --output, so
+        /// it cannot be combined with any other --output
         #[structopt(short, long)]
         oneline: bool,
+        #[structopt(flatten)]
+        filter: DeviceFilter,
     },
     /// Get the full record of your device
-    /// by providing app_eui, app_key, and dev_eui
+    /// by providing app_eui, app_key, and dev_eui, or one of
+    /// --dev-eui, --name or --label
     Get {
-        app_eui: String,
-        app_key: String,
-        dev_eui: String,
+        app_eui: Option<AppEui>,
+        app_key: Option<AppKey>,
+        dev_eui: Option<DevEui>,
+        #[structopt(flatten)]
+        select: SelectDevice,
     },
     /// Delete a device
-    /// by providing app_eui, app_key, and dev_eui
+    /// by providing app_eui, app_key, and dev_eui, or one of
+    /// --dev-eui, --name or --label
     Delete {
-        app_eui: String,
-        app_key: String,
-        dev_eui: String,
+        app_eui: Option<AppEui>,
+        app_key: Option<AppKey>,
+        dev_eui: Option<DevEui>,
+        #[structopt(flatten)]
+        select: SelectDevice,
     },
     /// Get the full record of your device
     /// by the UUID
-    GetById {
-        id: String,
-    },
+    GetById { id: String },
     /// Delete a device
     /// by the UUID
-    DeleteById {
+    DeleteById { id: String },
+    /// Update a device by the UUID
+    /// Only the provided fields are changed; use --active false to pause a device
+    Update {
         id: String,
+        /// Rename the device
+        #[structopt(long)]
+        name: Option<String>,
+        /// Activate (true) or pause (false) the device
+        #[structopt(long)]
+        active: Option<bool>,
+        #[structopt(long)]
+        adr_allowed: Option<bool>,
+        #[structopt(long)]
+        cf_list_enabled: Option<bool>,
+        /// UUID of the configuration profile to apply
+        #[structopt(long)]
+        config_profile_id: Option<String>,
+        /// RX1 delay in seconds
+        #[structopt(long)]
+        rx_delay: Option<u8>,
     },
     /// Create a device
     /// by providing app_eui, app_key, dev_eui and name
     Create {
-        app_eui: String,
-        app_key: String,
-        dev_eui: String,
+        app_eui: AppEui,
+        app_key: AppKey,
+        dev_eui: DevEui,
         name: String,
     },
     /// Create a device by app_eui and name prefix
     /// DevEui & AppKey are randomly generated
     /// Last four characters of DevEui are appended
-    CreateByAppEui {
-        app_eui: String,
-        name: String,
+    CreateByAppEui { app_eui: AppEui, name: String },
+    /// Create devices in bulk from a CSV, JSON or NDJSON file
+    /// Every row needs app_eui, app_key, dev_eui and name, and may list labels
+    /// (semicolon separated in CSV, an array in JSON). All rows are validated
+    /// before anything is sent, and a per-row result report is written at the end
+    /// Exports of TTN v2, The Things Stack and ChirpStack, and generic
+    /// DevEUI/JoinEUI/AppKey lists, are read with --format; devices without
+    /// an AppKey (ABP) are skipped
+    Import {
+        /// File to read; --from-file is an alias
+        #[structopt(long, alias = "from-file", parse(from_os_str))]
+        file: PathBuf,
+        /// csv, json or ndjson, guessed from the file extension when omitted,
+        /// or ttn-v2, ttn-v3, chirpstack or eui-csv
+        #[structopt(long)]
+        format: Option<import::Format>,
+        /// AppEUI for exported devices that have none, as in ChirpStack dumps.
+        /// Only for --format ttn-v2, ttn-v3, chirpstack or eui-csv
+        #[structopt(long)]
+        app_eui: Option<AppEui>,
+        /// Label exported devices with the name of their source application;
+        /// combine with --create-missing for labels that do not exist yet.
+        /// Only for --format ttn-v2, ttn-v3, chirpstack or eui-csv
+        #[structopt(long)]
+        label_with_app: bool,
+        /// Maximum number of devices created at the same time
+        #[structopt(long, default_value = "4")]
+        concurrency: usize,
+        /// Where to write the report; defaults to <file>.report.csv
+        #[structopt(long, parse(from_os_str))]
+        report: Option<PathBuf>,
+        /// Create labels that do not exist yet instead of rejecting the file
+        #[structopt(long)]
+        create_missing: bool,
     },
-    // Add a label to the device
-    // by providing device_uuid and label_uuid
-    AddLabel {
-        device: String,
-        label: String,
+    /// Export all devices for spreadsheets or version control
+    /// Label UUIDs are replaced by label names. The app_key column is
+    /// only exported together with --reveal-keys
+    Export {
+        /// csv, json, ndjson or yaml
+        #[structopt(long, default_value = "csv")]
+        format: export::Format,
+        /// Comma separated columns, e.g. name,dev_eui,labels,last_connected
+        /// Defaults to every column except app_key
+        #[structopt(long)]
+        columns: Option<export::Columns>,
+        /// Write to this file instead of stdout
+        #[structopt(long, parse(from_os_str))]
+        file: Option<PathBuf>,
     },
-    // Remove a label from device
-    // by providing device_uuid and label_uuid
-    RemoveLabel {
+    /// Add a label to the device
+    /// by providing device_uuid and a label name or uuid
+    AddLabel {
         device: String,
         label: String,
+        /// Create the label if no label has this name
+        #[structopt(long)]
+        create_missing: bool,
     },
+    /// Remove a label from device
+    /// by providing device_uuid and a label name or uuid
+    RemoveLabel { device: String, label: String },
+}
+
+/// Finds a device in the device list instead of by its credentials.
+/// Exactly one device has to match.
+#[derive(StructOpt, Debug)]
+pub struct SelectDevice {
+    /// The device with this DevEUI
+    #[structopt(long = "dev-eui", conflicts_with_all = &["by-name", "by-label"])]
+    pub by_dev_eui: Option<DevEui>,
+    /// The device with this name
+    #[structopt(long = "name", conflicts_with = "by-label")]
+    pub by_name: Option<String>,
+    /// The device carrying this label, given by name or id
+    #[structopt(long = "label")]
+    pub by_label: Option<String>,
 }
 
 #[derive(StructOpt, Debug)]
 pub enum DevicesCmd {
-    // No arguments required, but added for consistency
-    // and to disambiguate from `help`
-    All,
+    /// Stream all devices as newline-delimited JSON
+    /// Devices are printed as they arrive unless --sort-by is given
+    All {
+        #[structopt(flatten)]
+        filter: DeviceFilter,
+    },
 }
 
 #[derive(StructOpt, Debug)]
 pub enum LabelCmd {
     /// List all your organization's labels
     List,
-    /// Delete a label by id
-    DeleteById { id: String },
+    /// Get a label by name or id
+    Get { label: String },
+    /// Delete a label by name or id
+    #[structopt(alias = "delete-by-id")]
+    Delete { label: String },
     /// Create a device
     /// by providing a label name
     Create { name: String },
+    /// Rename a label, given by name or id
+    Rename { label: String, name: String },
+    /// Stream the devices carrying a label, given by name or id,
+    /// as newline-delimited JSON
+    Devices { label: String },
 }
 
 #[derive(StructOpt, Debug)]
 pub enum TtnCmd {
     /// Imports devices from your TTN Account
     /// (requires ttnctl access code at https://account.thethingsnetwork.org/)
-    Import,
+    /// Asks for anything not given by flags; with --yes nothing is asked
+    Import(TtnImport),
+    /// Imports devices from The Things Stack (TTN v3) with an API key that
+    /// may list applications and devices and read device keys.
+    /// Runs through the same pipeline as `device import`
+    ImportV3(TtnImportV3),
+}
+
+/// Options shared by the importers that read another network server
+#[derive(StructOpt, Debug)]
+pub struct SourceImport {
+    /// Maximum number of devices created at the same time
+    #[structopt(long, default_value = "4")]
+    pub concurrency: usize,
+    /// Write a per-device report, as CSV, JSON or NDJSON by extension
+    #[structopt(long, parse(from_os_str))]
+    pub report: Option<PathBuf>,
+}
+
+/// Read when --tts-api-key is not given
+pub const TTS_API_KEY_ENV: &str = "TTS_API_KEY";
+/// Read when --cluster-url is not given
+pub const TTS_CLUSTER_URL_ENV: &str = "TTS_CLUSTER_URL";
+
+#[derive(StructOpt, Debug)]
+pub struct TtnImportV3 {
+    /// TTS cluster, e.g. https://nam1.cloud.thethings.network; defaults to
+    /// $TTS_CLUSTER_URL, then https://eu1.cloud.thethings.network
+    #[structopt(long)]
+    pub cluster_url: Option<String>,
+    /// TTS API key; defaults to $TTS_API_KEY
+    #[structopt(long)]
+    pub tts_api_key: Option<String>,
+    /// Comma separated IDs of the TTS applications to import
+    #[structopt(
+        long,
+        use_delimiter = true,
+        conflicts_with = "all-apps",
+        required_unless = "all-apps"
+    )]
+    pub apps: Vec<String>,
+    /// Import every application the API key can see
+    #[structopt(long)]
+    pub all_apps: bool,
+    /// Label every imported device with its TTS application ID
+    #[structopt(long)]
+    pub label_with_app_id: bool,
+    #[structopt(flatten)]
+    pub import: SourceImport,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ChirpstackCmd {
+    /// Imports the OTAA devices of ChirpStack (v3) applications with an
+    /// API token. Runs through the same pipeline as `device import`
+    Import(ChirpstackImport),
+}
+
+/// Read when --server-url is not given
+pub const CHIRPSTACK_SERVER_URL_ENV: &str = "CHIRPSTACK_SERVER_URL";
+/// Read when --api-token is not given
+pub const CHIRPSTACK_API_TOKEN_ENV: &str = "CHIRPSTACK_API_TOKEN";
+
+#[derive(StructOpt, Debug)]
+pub struct ChirpstackImport {
+    /// ChirpStack Application Server, e.g. http://localhost:8080; defaults
+    /// to $CHIRPSTACK_SERVER_URL
+    #[structopt(long)]
+    pub server_url: Option<String>,
+    /// ChirpStack API token; defaults to $CHIRPSTACK_API_TOKEN
+    #[structopt(long)]
+    pub api_token: Option<String>,
+    /// AppEUI (JoinEUI) the devices were programmed with, which ChirpStack
+    /// does not store
+    #[structopt(long)]
+    pub app_eui: AppEui,
+    /// Comma separated IDs of the ChirpStack applications to import
+    #[structopt(
+        long,
+        use_delimiter = true,
+        conflicts_with = "all-applications",
+        required_unless = "all-applications"
+    )]
+    pub applications: Vec<String>,
+    /// Import every application the token can see
+    #[structopt(long)]
+    pub all_applications: bool,
+    /// With --all-applications, only those of this organization
+    #[structopt(long)]
+    pub organization_id: Option<String>,
+    /// Label every imported device with its application name or device
+    /// profile name: application or device-profile
+    #[structopt(long)]
+    pub label_with: Option<ChirpstackLabel>,
+    #[structopt(flatten)]
+    pub import: SourceImport,
+}
+
+/// What ChirpStack imports are labelled with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChirpstackLabel {
+    Application,
+    DeviceProfile,
+}
+
+impl FromStr for ChirpstackLabel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<ChirpstackLabel, String> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "application" => Ok(ChirpstackLabel::Application),
+            "device-profile" => Ok(ChirpstackLabel::DeviceProfile),
+            other => Err(format!(
+                "unknown label source \"{}\"; expected application or device-profile",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TtnImport {
+    /// Single use ttnctl access code
+    #[structopt(long)]
+    pub access_code: Option<String>,
+    /// Comma separated IDs of the TTN applications to import
+    #[structopt(long, use_delimiter = true, conflicts_with = "all-apps")]
+    pub apps: Vec<String>,
+    /// Import every TTN application of the account
+    #[structopt(long)]
+    pub all_apps: bool,
+    /// Label every imported device with its TTN application ID
+    #[structopt(long)]
+    pub label_with_app_id: bool,
+    /// never, always or prompt (per device)
+    #[structopt(long)]
+    pub delete_from_ttn: Option<DeleteFromTtn>,
+    /// Import every device without asking. Devices are only labelled or
+    /// deleted from TTN when the flags above say so
+    #[structopt(short, long)]
+    pub yes: bool,
+    /// TTN account server; defaults to https://account.thethingsnetwork.org
+    #[structopt(long)]
+    pub account_url: Option<String>,
+    /// Handler to look applications up on, for a private TTN deployment.
+    /// May be repeated; defaults to the public TTN handlers
+    #[structopt(long = "handler-url")]
+    pub handler_urls: Vec<String>,
+}
+
+/// Whether imported devices are removed from TTN, which avoids both
+/// networks answering the same join request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeleteFromTtn {
+    Never,
+    Always,
+    Prompt,
+}
+
+impl FromStr for DeleteFromTtn {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<DeleteFromTtn, String> {
+        match s.to_lowercase().as_str() {
+            "never" => Ok(DeleteFromTtn::Never),
+            "always" => Ok(DeleteFromTtn::Always),
+            "prompt" => Ok(DeleteFromTtn::Prompt),
+            other => Err(format!(
+                "unknown value \"{}\"; expected never, always or prompt",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ConfigCmd {
+    /// Create a profile (see --profile) in ~/.config/helium-console/config.toml
+    /// Takes the key, base URL and timeout from --api-key, --base-url and
+    /// --timeout or their env vars, and asks for the API key if neither is set
+    Init {
+        /// Replace the profile if it already exists
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Print the settings in effect, with the API key masked, and where
+    /// the key, base URL and timeout came from
+    Show,
+    /// Change one setting of the selected profile, e.g. `set base_url <url>`
+    /// Retry settings are named retry.<setting>, e.g. retry.max_attempts
+    Set { field: String, value: String },
+    /// Check the syntax and API key of every profile
+    Validate,
+    /// Print the organization the selected profile's API key belongs to
+    Whoami,
 }
@@ -1,11 +1,19 @@
-use super::{Error, Result};
+use super::Result;
 use helium_console::client::Config;
+use serde_derive::{Deserialize, Serialize};
 use std::{
-    fs,
-    io::{stdin, Write},
-    path::Path,
+    collections::BTreeMap,
+    env, fmt, fs,
+    io::stdin,
+    path::{Path, PathBuf},
 };
 
+/// Config file used before profiles, read from the working directory
+/// when there is no file at the XDG location
+const LEGACY_PATH: &str = ".helium-console-config.toml";
+const DEFAULT_PROFILE: &str = "default";
+const PROFILE_ENV: &str = "HELIUM_CONSOLE_PROFILE";
+
 pub fn get_input(prompt: &str) -> String {
     print!("{}\r\n", prompt);
     let mut input = String::new();
@@ -16,24 +24,376 @@ pub fn get_input(prompt: &str) -> String {
     input.trim().to_string()
 }
 
-pub fn load(path: &str) -> Result<Config> {
-    if !Path::new(path).exists() {
-        let mut file = fs::File::create(path)?;
-        let key = get_input("Enter API key");
+/// Contents of the config file: one Console connection per profile
+///
+/// ```toml
+/// default_profile = "prod"
+///
+/// [profiles.prod]
+/// key = "..."
+/// base_url = "https://console.helium.com"
+/// request_timeout = 120
+/// ```
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ConfigFile {
+    /// Profile used when neither --profile nor HELIUM_CONSOLE_PROFILE is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Config>,
+}
+
+impl ConfigFile {
+    /// Reads a config file. A file without profiles, as written by older
+    /// versions, is read as a single "default" profile.
+    pub fn read(path: &Path) -> Result<ConfigFile> {
+        let contents = fs::read_to_string(path)?;
+        let value: toml::Value = toml::from_str(&contents)?;
+        if value.get("key").is_some() {
+            let mut file = ConfigFile::default();
+            file.profiles
+                .insert(DEFAULT_PROFILE.to_string(), value.try_into()?);
+            Ok(file)
+        } else {
+            Ok(value.try_into()?)
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Name of the profile to use: the one asked for, then the
+    /// HELIUM_CONSOLE_PROFILE env var, then `default_profile`
+    pub fn profile_name(&self, requested: Option<&str>) -> String {
+        requested
+            .map(str::to_string)
+            .or_else(|| env::var(PROFILE_ENV).ok())
+            .or_else(|| self.default_profile.clone())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Config> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| self.missing_profile(name))
+    }
+
+    fn missing_profile(&self, name: &str) -> Box<dyn std::error::Error> {
+        let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+        format!(
+            "No profile named \"{}\" in the config file. Available profiles: {}",
+            name,
+            names.join(", ")
+        )
+        .into()
+    }
+}
+
+/// `$XDG_CONFIG_HOME/helium-console/config.toml`, or
+/// `~/.config/helium-console/config.toml` when XDG_CONFIG_HOME is not set
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .or_else(|| env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".config"))
+        })?;
+    Some(config_home.join("helium-console").join("config.toml"))
+}
+
+/// The config file in use: the XDG location if it exists, otherwise the
+/// legacy file in the working directory if that exists
+pub fn find() -> Option<PathBuf> {
+    if let Some(path) = default_path() {
+        if path.exists() {
+            return Some(path);
+        }
+    }
+    let legacy = PathBuf::from(LEGACY_PATH);
+    if legacy.exists() {
+        Some(legacy)
+    } else {
+        None
+    }
+}
+
+/// Settings given as flags, which win over env vars and the profile
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+    pub timeout: Option<u64>,
+}
+
+const API_KEY_ENV: &str = "HELIUM_CONSOLE_API_KEY";
+const BASE_URL_ENV: &str = "HELIUM_CONSOLE_BASE_URL";
+const TIMEOUT_ENV: &str = "HELIUM_CONSOLE_TIMEOUT";
+
+/// Where a setting came from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Flag(&'static str),
+    Env(&'static str),
+    Profile(String),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Flag(flag) => write!(f, "flag {}", flag),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Profile(name) => write!(f, "profile \"{}\"", name),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// First of flag, then env var, with its source
+fn flag_or_env<T>(
+    flag: Option<T>,
+    flag_name: &'static str,
+    env_name: &'static str,
+    parse: impl Fn(String) -> Result<T>,
+) -> Result<Option<(T, Source)>> {
+    if let Some(value) = flag {
+        return Ok(Some((value, Source::Flag(flag_name))));
+    }
+    match env_var(env_name) {
+        Some(value) => Ok(Some((parse(value)?, Source::Env(env_name)))),
+        None => Ok(None),
+    }
+}
+
+impl Overrides {
+    fn api_key(&self) -> Result<Option<(String, Source)>> {
+        flag_or_env(self.api_key.clone(), "--api-key", API_KEY_ENV, Ok)
+    }
+
+    fn base_url(&self) -> Result<Option<(String, Source)>> {
+        flag_or_env(self.base_url.clone(), "--base-url", BASE_URL_ENV, Ok)
+    }
+
+    fn timeout(&self) -> Result<Option<(u64, Source)>> {
+        flag_or_env(self.timeout, "--timeout", TIMEOUT_ENV, |value| {
+            value.parse().map_err(|_| {
+                format!(
+                    "{} must be a number of seconds [input: {}]",
+                    TIMEOUT_ENV, value
+                )
+                .into()
+            })
+        })
+    }
+}
+
+/// The settings in effect for a command: the selected profile, if any,
+/// with flags and env vars applied on top
+#[derive(Debug)]
+pub struct Resolved {
+    pub config: Config,
+    pub path: Option<PathBuf>,
+    pub profile: String,
+    /// Source of the key, base_url and request_timeout settings
+    pub sources: Vec<(&'static str, Source)>,
+}
 
-        // verify API key
-        let key_verify = base64::decode(&key)?;
-        if key_verify.len() != 32 {
-            println!("Invalid API key ipnut");
-            return Err(Error::InvalidApiKey.into());
+/// Applies flag > env > profile > default to the selected profile.
+/// Without a config file, an API key from a flag or env var is enough.
+pub fn resolve(profile: Option<&str>, overrides: &Overrides) -> Result<Resolved> {
+    let path = find();
+    let file = match &path {
+        Some(path) => ConfigFile::read(path)?,
+        None => ConfigFile::default(),
+    };
+    let name = file.profile_name(profile);
+    let explicit = profile.is_some() || env_var(PROFILE_ENV).is_some();
+    let fallback = if file.profiles.contains_key(&name) {
+        Source::Profile(name.clone())
+    } else {
+        Source::Default
+    };
+
+    let (mut config, key_source) = match (overrides.api_key()?, file.profiles.get(&name)) {
+        (_, None) if explicit => return Err(file.missing_profile(&name)),
+        (Some((key, source)), Some(config)) => (config.clone().with_key(key), source),
+        (Some((key, source)), None) => (Config::new(key), source),
+        (None, Some(config)) => (config.clone(), fallback.clone()),
+        (None, None) => return Err(file.missing_profile(&name)),
+    };
+    let base_url_source = match overrides.base_url()? {
+        Some((base_url, source)) => {
+            config = config.with_base_url(&base_url);
+            source
+        }
+        None => fallback.clone(),
+    };
+    let timeout_source = match overrides.timeout()? {
+        Some((timeout, source)) => {
+            config = config.with_request_timeout(timeout);
+            source
         }
+        None => fallback,
+    };
+
+    Ok(Resolved {
+        config,
+        path,
+        profile: name,
+        sources: vec![
+            ("key", key_source),
+            ("base_url", base_url_source),
+            ("request_timeout", timeout_source),
+        ],
+    })
+}
+
+/// Loads the settings for a command. Without a config file or an API key
+/// override, asks for an API key and saves it as a profile first.
+pub fn load(profile: Option<&str>, overrides: &Overrides) -> Result<Config> {
+    if find().is_none() && overrides.api_key()?.is_none() {
+        init(profile, overrides, false)?;
+    }
+    Ok(resolve(profile, overrides)?.config)
+}
+
+/// The config file in use, or an error pointing at `config init`
+fn require() -> Result<PathBuf> {
+    find().ok_or_else(|| "No config file found. Run `helium-console-cli config init`".into())
+}
+
+/// Creates or replaces a profile at the XDG location from the overrides,
+/// asking for the API key when none is given. Profiles from a legacy
+/// file are carried over. Nothing is written unless the new profile is valid.
+pub fn init(profile: Option<&str>, overrides: &Overrides, force: bool) -> Result<PathBuf> {
+    let path = default_path().ok_or("Cannot find a home directory for the config file")?;
+    let mut file = match find() {
+        Some(existing) => ConfigFile::read(&existing)?,
+        None => ConfigFile::default(),
+    };
+    let name = file.profile_name(profile);
+    if file.profiles.contains_key(&name) && !force {
+        return Err(format!(
+            "Profile \"{}\" already exists. Use --force to replace it, or `config set` to change it",
+            name
+        )
+        .into());
+    }
 
-        let config = Config::new(key);
+    let key = match overrides.api_key()? {
+        Some((key, _)) => key,
+        None => get_input("Enter API key"),
+    };
+    let mut config = Config::new(key);
+    if let Some((base_url, _)) = overrides.base_url()? {
+        config = config.with_base_url(&base_url);
+    }
+    if let Some((timeout, _)) = overrides.timeout()? {
+        config = config.with_request_timeout(timeout);
+    }
+    config.validate()?;
+
+    file.profiles.insert(name.clone(), config);
+    file.write(&path)?;
+    println!("Profile \"{}\" saved to {}", name, path.display());
+    Ok(path)
+}
+
+/// Prints the settings in effect as TOML, with the key masked unless
+/// `reveal_key` is set, followed by where each overridable setting came from
+pub fn show(profile: Option<&str>, overrides: &Overrides, reveal_key: bool) -> Result {
+    let resolved = resolve(profile, overrides)?;
+    let mut value = toml::Value::try_from(&resolved.config)?;
+    if !reveal_key {
+        value["key"] = toml::Value::String("********".to_string());
+    }
+    match &resolved.path {
+        Some(path) => println!("# {} [profile: {}]", path.display(), resolved.profile),
+        None => println!("# no config file"),
+    }
+    print!("{}", toml::to_string(&value)?);
+    println!("\n# sources");
+    for (setting, source) in &resolved.sources {
+        println!("#   {}: {}", setting, source);
+    }
+    Ok(())
+}
+
+/// Changes one setting of the selected profile. `field` is a setting name
+/// such as `base_url`, or `retry.<name>` for the retry policy, and `value`
+/// is parsed as the type that setting already has.
+pub fn set(profile: Option<&str>, field: &str, value: &str) -> Result {
+    let path = require()?;
+    let mut file = ConfigFile::read(&path)?;
+    let name = file.profile_name(profile);
+    let mut config = toml::Value::try_from(file.profile(&name)?)?;
 
-        file.write_all(&toml::to_string(&config)?.as_bytes())?;
+    let mut target = &mut config;
+    for part in field.split('.') {
+        target = target
+            .get_mut(part)
+            .ok_or_else(|| format!("Unknown setting \"{}\"", field))?;
     }
+    *target = match target {
+        toml::Value::String(_) => toml::Value::String(value.to_string()),
+        toml::Value::Integer(_) => toml::Value::Integer(value.parse()?),
+        toml::Value::Float(_) => toml::Value::Float(value.parse()?),
+        toml::Value::Boolean(_) => toml::Value::Boolean(value.parse()?),
+        toml::Value::Array(_) => toml::Value::Array(
+            value
+                .split(',')
+                .map(|item| item.trim().parse().map(toml::Value::Integer))
+                .collect::<std::result::Result<_, _>>()?,
+        ),
+        _ => return Err(format!("\"{}\" cannot be set directly", field).into()),
+    };
 
-    let contents = fs::read_to_string(path)?;
-    let config: Config = toml::from_str(&contents)?;
-    Ok(config)
+    let config: Config = config.try_into()?;
+    config.validate()?;
+    file.profiles.insert(name.clone(), config);
+    file.write(&path)?;
+    println!("Set {} for profile \"{}\"", field, name);
+    Ok(())
+}
+
+/// Parses the config file and checks every profile the way `Client::new` would
+pub fn validate() -> Result {
+    let path = require()?;
+    let file = ConfigFile::read(&path)?;
+    println!("{}", path.display());
+
+    let mut invalid = 0;
+    for (name, config) in &file.profiles {
+        match config.validate() {
+            Ok(()) => println!("  {}: ok", name),
+            Err(err) => {
+                invalid += 1;
+                println!("  {}: {}", name, err);
+            }
+        }
+    }
+    if let Some(default_profile) = &file.default_profile {
+        if !file.profiles.contains_key(default_profile) {
+            invalid += 1;
+            println!("  default_profile \"{}\" does not exist", default_profile);
+        }
+    }
+
+    if invalid == 0 {
+        println!("Config is valid");
+        Ok(())
+    } else {
+        Err(format!("{} problem(s) found in the config file", invalid).into())
+    }
 }
@@ -0,0 +1,209 @@
+use super::{output::Printer, Result};
+use futures::TryStreamExt;
+use helium_console::client::Client;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use serde_json::Value;
+use std::{io::Write, str::FromStr};
+
+/// Every field of a detailed device record, in export order
+pub const COLUMNS: &[&str] = &[
+    "id",
+    "name",
+    "dev_eui",
+    "app_eui",
+    "app_key",
+    "organization_id",
+    "oui",
+    "active",
+    "adr_allowed",
+    "cf_list_enabled",
+    "config_profile_id",
+    "dc_usage",
+    "in_xor_filter",
+    "labels",
+    "last_connected",
+    "rx_delay",
+    "total_packets",
+];
+
+/// Columns holding key material, only exported with `--reveal-keys`
+const KEY_COLUMNS: &[&str] = &["app_key"];
+
+/// File formats written by `device export`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+    Ndjson,
+    Yaml,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Format, String> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "ndjson" | "jsonl" => Ok(Format::Ndjson),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            other => Err(format!(
+                "unknown format \"{}\"; expected csv, json, ndjson or yaml",
+                other
+            )),
+        }
+    }
+}
+
+/// Comma separated list of columns, checked against `COLUMNS`
+#[derive(Clone, Debug)]
+pub struct Columns(Vec<String>);
+
+impl FromStr for Columns {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Columns, String> {
+        let mut columns = Vec::new();
+        for column in s.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            if !COLUMNS.contains(&column) {
+                return Err(format!(
+                    "unknown column \"{}\"; expected one of {}",
+                    column,
+                    COLUMNS.join(",")
+                ));
+            }
+            columns.push(column.to_string());
+        }
+        if columns.is_empty() {
+            return Err("at least one column is required".to_string());
+        }
+        Ok(Columns(columns))
+    }
+}
+
+impl Columns {
+    /// Refuses key columns unless keys are revealed
+    pub fn check(&self, printer: &Printer) -> Result {
+        if !printer.reveals_keys() {
+            if let Some(column) = self.0.iter().find(|c| KEY_COLUMNS.contains(&c.as_str())) {
+                return Err(format!("Exporting {} requires --reveal-keys", column).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Columns {
+    /// Everything but key material
+    fn default() -> Columns {
+        Columns(
+            COLUMNS
+                .iter()
+                .filter(|column| !KEY_COLUMNS.contains(column))
+                .map(|column| column.to_string())
+                .collect(),
+        )
+    }
+}
+
+/// One exported device: the selected columns, in the requested order
+struct Record<'a> {
+    columns: &'a [String],
+    values: Vec<Value>,
+}
+
+impl<'a> Serialize for Record<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for (column, value) in self.columns.iter().zip(&self.values) {
+            map.serialize_entry(column, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'a> Record<'a> {
+    fn csv_fields(&self) -> Vec<String> {
+        self.values
+            .iter()
+            .map(|value| match value {
+                Value::Null => String::new(),
+                Value::String(s) => s.clone(),
+                // labels, joined the same way `device import` splits them
+                Value::Array(items) => items
+                    .iter()
+                    .map(|item| match item {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(";"),
+                other => other.to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Writes every device of the organization to `out`.
+/// CSV and NDJSON are written as pages arrive; JSON and YAML are
+/// written once all devices have been fetched.
+pub async fn export(
+    client: &mut Client,
+    printer: Printer,
+    format: Format,
+    columns: Columns,
+    out: &mut dyn Write,
+) -> Result {
+    columns.check(&printer)?;
+    let columns = columns.0;
+
+    client.get_labels().await?;
+    let label_names = client.label_names();
+    let to_record = |device| -> Result<Record> {
+        let mut device = printer.value(&device)?;
+        if let Some(Value::Array(labels)) = device.get_mut("labels") {
+            for label in labels.iter_mut() {
+                if let Some(name) = label.as_str().and_then(|id| label_names.get(id)) {
+                    *label = Value::String(name.clone());
+                }
+            }
+        }
+        let values = columns
+            .iter()
+            .map(|column| device.get(column).cloned().unwrap_or(Value::Null))
+            .collect();
+        Ok(Record {
+            columns: &columns,
+            values,
+        })
+    };
+
+    let mut devices = client.stream_detailed_devices();
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(&columns)?;
+            while let Some(device) = devices.try_next().await? {
+                writer.write_record(to_record(device)?.csv_fields())?;
+            }
+            writer.flush()?;
+        }
+        Format::Ndjson => {
+            while let Some(device) = devices.try_next().await? {
+                writeln!(out, "{}", serde_json::to_string(&to_record(device)?)?)?;
+            }
+        }
+        Format::Json | Format::Yaml => {
+            let mut records = Vec::new();
+            while let Some(device) = devices.try_next().await? {
+                records.push(to_record(device)?);
+            }
+            if format == Format::Json {
+                writeln!(out, "{}", serde_json::to_string_pretty(&records)?)?;
+            } else {
+                writeln!(out, "{}", serde_yaml::to_string(&records)?)?;
+            }
+        }
+    }
+    Ok(())
+}
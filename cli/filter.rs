@@ -0,0 +1,303 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use helium_console::{AppEui, DetailedDevice, Label};
+use std::{cmp::Ordering, str::FromStr};
+use structopt::StructOpt;
+
+/// Narrows down and orders a device listing. The Console API has no
+/// filter or sort parameters, so everything is applied to the fetched list.
+#[derive(StructOpt, Debug)]
+pub struct DeviceFilter {
+    /// Only active (true) or paused (false) devices
+    #[structopt(long)]
+    active: Option<bool>,
+    /// Only devices that are (true) or are not (false) in the XOR filter
+    #[structopt(long)]
+    in_xor_filter: Option<bool>,
+    /// Only devices carrying this label, given by name or id
+    #[structopt(long)]
+    pub label: Option<String>,
+    /// Only devices last connected longer ago than this, e.g. 30m, 12h or 7d.
+    /// Devices that never connected are included
+    #[structopt(long)]
+    last_connected_before: Option<Age>,
+    /// Only devices that used at least this many data credits
+    #[structopt(long)]
+    min_dc_usage: Option<usize>,
+    /// Only devices whose name matches this pattern; * matches any run of
+    /// characters and ? any single character
+    #[structopt(long)]
+    name_glob: Option<String>,
+    #[structopt(long)]
+    app_eui: Option<AppEui>,
+    /// name, dev_eui, last_connected, dc_usage or total_packets
+    #[structopt(long)]
+    sort_by: Option<SortKey>,
+    /// Print at most this many devices
+    #[structopt(long)]
+    limit: Option<usize>,
+}
+
+impl DeviceFilter {
+    /// Whether devices can be printed as they arrive, rather than all at once
+    pub fn streams(&self) -> bool {
+        self.sort_by.is_none()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(usize::MAX)
+    }
+
+    /// `label` is the resolved `--label`, if one was given
+    pub fn matches(&self, device: &DetailedDevice, label: Option<&Label>) -> bool {
+        if let Some(active) = self.active {
+            if device.active() != active {
+                return false;
+            }
+        }
+        if let Some(in_xor_filter) = self.in_xor_filter {
+            if device.in_xor_filter() != in_xor_filter {
+                return false;
+            }
+        }
+        if let Some(label) = label {
+            if !device.labels().contains(label.id()) {
+                return false;
+            }
+        }
+        if let Some(age) = &self.last_connected_before {
+            if let Some(last_connected) = last_connected(device) {
+                // an age reaching back past the calendar keeps nothing
+                match Utc::now().checked_sub_signed(age.0) {
+                    Some(cutoff) if last_connected < cutoff => (),
+                    _ => return false,
+                }
+            }
+        }
+        if let Some(min_dc_usage) = self.min_dc_usage {
+            if device.dc_usage() < min_dc_usage {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.name_glob {
+            if !glob_match(pattern, device.name()) {
+                return false;
+            }
+        }
+        if let Some(app_eui) = &self.app_eui {
+            if device.app_eui() != app_eui {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Keeps the matching devices, sorted by `--sort-by` and cut at `--limit`
+    pub fn apply(
+        &self,
+        devices: Vec<DetailedDevice>,
+        label: Option<&Label>,
+    ) -> Vec<DetailedDevice> {
+        let mut devices: Vec<DetailedDevice> = devices
+            .into_iter()
+            .filter(|device| self.matches(device, label))
+            .collect();
+        if let Some(key) = self.sort_by {
+            devices.sort_by(|a, b| key.compare(a, b));
+        }
+        devices.truncate(self.limit());
+        devices
+    }
+}
+
+/// Device fields a listing can be sorted by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    Name,
+    DevEui,
+    /// Never connected first, then oldest first
+    LastConnected,
+    /// Highest first
+    DcUsage,
+    /// Highest first
+    TotalPackets,
+}
+
+impl SortKey {
+    fn compare(self, a: &DetailedDevice, b: &DetailedDevice) -> Ordering {
+        match self {
+            SortKey::Name => a.name().cmp(b.name()),
+            SortKey::DevEui => a.dev_eui().to_string().cmp(&b.dev_eui().to_string()),
+            SortKey::LastConnected => last_connected(a).cmp(&last_connected(b)),
+            SortKey::DcUsage => b.dc_usage().cmp(&a.dc_usage()),
+            SortKey::TotalPackets => b.total_packets().cmp(&a.total_packets()),
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<SortKey, String> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "name" => Ok(SortKey::Name),
+            "dev_eui" => Ok(SortKey::DevEui),
+            "last_connected" => Ok(SortKey::LastConnected),
+            "dc_usage" => Ok(SortKey::DcUsage),
+            "total_packets" => Ok(SortKey::TotalPackets),
+            other => Err(format!(
+                "unknown sort key \"{}\"; expected name, dev_eui, last_connected, dc_usage or total_packets",
+                other
+            )),
+        }
+    }
+}
+
+/// A span of time such as `90s`, `30m`, `12h`, `7d` or `2w`
+#[derive(Clone, Debug)]
+pub struct Age(Duration);
+
+impl FromStr for Age {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Age, String> {
+        let invalid = || format!("invalid duration \"{}\"; expected e.g. 30m, 12h or 7d", s);
+        let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let (amount, unit) = s.split_at(split);
+        let amount: i64 = amount.parse().map_err(|_| invalid())?;
+        let duration = match unit {
+            "s" => Duration::try_seconds(amount),
+            "m" => Duration::try_minutes(amount),
+            "h" => Duration::try_hours(amount),
+            "d" => Duration::try_days(amount),
+            "w" => Duration::try_weeks(amount),
+            _ => return Err(invalid()),
+        };
+        match duration.filter(|duration| Utc::now().checked_sub_signed(*duration).is_some()) {
+            Some(duration) => Ok(Age(duration)),
+            None => Err(format!("duration \"{}\" is too long", s)),
+        }
+    }
+}
+
+fn last_connected(device: &DetailedDevice) -> Option<DateTime<Utc>> {
+    parse_timestamp(device.last_connected()?)
+}
+
+/// The server sends RFC 3339 timestamps, sometimes without a UTC offset
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    if let Ok(parsed) = DateTime::parse_from_rfc3339(timestamp) {
+        return Some(parsed.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .map(|naive| DateTime::from_naive_utc_and_offset(naive, Utc))
+}
+
+/// Shell-style match where `*` is any run of characters and `?` any one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and of the text it was matched against
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn glob_matches_like_a_shell() {
+        for (pattern, text, expected) in &[
+            ("sensor-1", "sensor-1", true),
+            ("sensor-1", "sensor-10", false),
+            ("sensor-?", "sensor-1", true),
+            ("sensor-?", "sensor-", false),
+            ("sensor-*", "sensor-", true),
+            ("sensor-*", "sensor-north-12", true),
+            ("sensor-*", "gateway-1", false),
+            ("*a?c*", "abc", true),
+            ("*a?c*", "xxaxcyy", true),
+            ("*a?c*", "aac", true),
+            ("*a?c*", "ac", false),
+            ("*a?c*", "abbc", false),
+            ("**", "", true),
+            ("*", "anything", true),
+            ("", "", true),
+            ("", "sensor", false),
+        ] {
+            assert_eq!(
+                glob_match(pattern, text),
+                *expected,
+                "{:?} against {:?}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn parses_ages() {
+        for (input, expected) in &[
+            ("90s", Duration::seconds(90)),
+            ("30m", Duration::minutes(30)),
+            ("12h", Duration::hours(12)),
+            ("7d", Duration::days(7)),
+            ("2w", Duration::weeks(2)),
+            ("0d", Duration::zero()),
+        ] {
+            assert_eq!(input.parse::<Age>().unwrap().0, *expected, "{:?}", input);
+        }
+        for input in &[
+            "",
+            "7",
+            "d",
+            "7y",
+            "7 d",
+            "7dd",
+            "-7d",
+            "1.5h",
+            "999999999999999w",
+            "99999999999999999999s",
+        ] {
+            assert!(input.parse::<Age>().is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn parses_timestamps_with_and_without_offset() {
+        let expected = Utc.with_ymd_and_hms(2021, 3, 4, 5, 6, 7).unwrap();
+        for input in &[
+            "2021-03-04T05:06:07Z",
+            "2021-03-04T05:06:07+00:00",
+            "2021-03-04T07:06:07+02:00",
+            "2021-03-04T05:06:07",
+        ] {
+            assert_eq!(parse_timestamp(input), Some(expected), "{:?}", input);
+        }
+        assert_eq!(
+            parse_timestamp("2021-03-04T05:06:07.250"),
+            Some(expected + Duration::milliseconds(250))
+        );
+        for input in &["", "yesterday", "2021-03-04", "2021-03-04 05:06:07"] {
+            assert_eq!(parse_timestamp(input), None, "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use super::{
+    clicmd::SourceImport,
+    sources::{self, Record, Records},
+    Result,
+};
+use futures::{stream, StreamExt};
+use helium_console::{ConsoleApi, ConsoleError, DeviceIndex, DeviceLabel, NewDevice};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// File formats accepted by `device import`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+    Ndjson,
+    /// TTN v2 handler API device listing
+    TtnV2,
+    /// The Things Stack `ttn-lw-cli` end device output
+    TtnV3,
+    /// ChirpStack device CSV dump
+    Chirpstack,
+    /// DevEUI, JoinEUI and AppKey columns, e.g. LoRa Alliance onboarding lists
+    EuiCsv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Format, String> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "ndjson" | "jsonl" => Ok(Format::Ndjson),
+            "ttn-v2" => Ok(Format::TtnV2),
+            "ttn-v3" | "tts" => Ok(Format::TtnV3),
+            "chirpstack" => Ok(Format::Chirpstack),
+            "eui-csv" => Ok(Format::EuiCsv),
+            other => Err(format!(
+                "unknown format \"{}\"; expected csv, json, ndjson, ttn-v2, ttn-v3, chirpstack or eui-csv",
+                other
+            )),
+        }
+    }
+}
+
+impl Format {
+    /// Guesses the format from the file extension
+    pub fn from_path(path: &Path) -> Option<Format> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    /// Whether this is the export of another network server rather than
+    /// a file in this tool's own format
+    fn is_export(self) -> bool {
+        !matches!(self, Format::Csv | Format::Json | Format::Ndjson)
+    }
+}
+
+/// One device as written in a JSON or NDJSON import file, and what the
+/// other formats are read into
+#[derive(Debug, Deserialize)]
+pub struct Row {
+    pub app_eui: String,
+    pub app_key: String,
+    pub dev_eui: String,
+    pub name: String,
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+/// One device as written in a CSV import file, where labels are
+/// separated by semicolons in a single column
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    app_eui: String,
+    app_key: String,
+    dev_eui: String,
+    name: String,
+    #[serde(default)]
+    labels: Option<String>,
+}
+
+impl From<CsvRow> for Row {
+    fn from(row: CsvRow) -> Row {
+        let labels = row
+            .labels
+            .unwrap_or_default()
+            .split(';')
+            .map(str::trim)
+            .filter(|label| !label.is_empty())
+            .map(str::to_string)
+            .collect();
+        Row {
+            app_eui: row.app_eui,
+            app_key: row.app_key,
+            dev_eui: row.dev_eui,
+            name: row.name,
+            labels,
+        }
+    }
+}
+
+/// A row that passed validation and is ready to be sent
+#[derive(Debug)]
+pub struct ImportRow {
+    /// 1-based position of the record in the file
+    pub row: usize,
+    pub device: NewDevice,
+    pub labels: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Created,
+    AlreadyExists,
+    Failed,
+    /// Left out of the import, such as ABP devices in an export
+    Skipped,
+}
+
+/// Outcome of importing one row
+#[derive(Debug, Serialize)]
+pub struct ReportRow {
+    pub row: usize,
+    pub dev_eui: String,
+    pub name: String,
+    pub status: Status,
+    pub device_id: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// Reads every record of `path`, returning each with its row number.
+/// Records that cannot be parsed at all are reported as errors.
+fn read_rows(path: &Path, format: Format, defaults: &sources::Defaults) -> Result<Records> {
+    let mut rows = Vec::new();
+    match format {
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_path(path)?;
+            for (index, record) in reader.deserialize::<CsvRow>().enumerate() {
+                rows.push((
+                    index + 1,
+                    Record::Row(record.map(Row::from).map_err(|e| e.to_string())),
+                ));
+            }
+        }
+        Format::Json => {
+            let contents = fs::read_to_string(path)?;
+            let records: Vec<serde_json::Value> = serde_json::from_str(&contents)?;
+            for (index, record) in records.into_iter().enumerate() {
+                rows.push((
+                    index + 1,
+                    Record::Row(serde_json::from_value(record).map_err(|e| e.to_string())),
+                ));
+            }
+        }
+        Format::Ndjson => {
+            let contents = fs::read_to_string(path)?;
+            for (index, line) in contents.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                rows.push((
+                    index + 1,
+                    Record::Row(serde_json::from_str(line).map_err(|e| e.to_string())),
+                ));
+            }
+        }
+        Format::TtnV2 => rows = sources::ttn_v2(path, defaults)?,
+        Format::TtnV3 => rows = sources::ttn_v3(path, defaults)?,
+        Format::Chirpstack => rows = sources::chirpstack(path, defaults)?,
+        Format::EuiCsv => rows = sources::eui_csv(path, defaults)?,
+    }
+    Ok(rows)
+}
+
+/// The rows of a file that passed validation, and the devices left out
+pub struct Validated {
+    pub rows: Vec<ImportRow>,
+    /// Report rows of the devices that cannot be migrated
+    pub skipped: Vec<ReportRow>,
+}
+
+/// Parses and validates every row of the file. Nothing is sent unless
+/// all rows are valid, so a typo on the last line does not leave a
+/// half-imported file behind. `defaults` only applies to exports of
+/// other network servers.
+pub fn validate(path: &Path, format: Format, defaults: &sources::Defaults) -> Result<Validated> {
+    if !format.is_export() && (defaults.app_eui.is_some() || defaults.label_with_app) {
+        return Err(
+            "--app-eui and --label-with-app only apply to exports of other network servers \
+             (ttn-v2, ttn-v3, chirpstack or eui-csv)"
+                .into(),
+        );
+    }
+
+    let mut valid = Vec::new();
+    let mut skipped = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+
+    for (row, record) in read_rows(path, format, defaults)? {
+        let record = match record {
+            Record::Row(Ok(record)) => record,
+            Record::Row(Err(e)) => {
+                errors.push(format!("row {}: {}", row, e));
+                continue;
+            }
+            Record::Skipped {
+                id,
+                dev_eui,
+                reason,
+            } => {
+                println!("Skipping row {} ({}): {}", row, id, reason);
+                skipped.push(ReportRow {
+                    row,
+                    dev_eui,
+                    name: id,
+                    status: Status::Skipped,
+                    device_id: None,
+                    reason: Some(reason),
+                });
+                continue;
+            }
+        };
+        if record.name.trim().is_empty() {
+            errors.push(format!("row {}: name is empty", row));
+            continue;
+        }
+        match NewDevice::from_user_input(
+            record.app_eui,
+            record.app_key,
+            record.dev_eui,
+            record.name,
+        ) {
+            Ok(device) => {
+                let credentials = (
+                    *device.app_eui(),
+                    *device.app_key().expose_secret(),
+                    *device.dev_eui(),
+                );
+                if !seen.insert(credentials) {
+                    errors.push(format!(
+                        "row {}: duplicate of an earlier row with dev_eui {}",
+                        row,
+                        device.dev_eui()
+                    ));
+                    continue;
+                }
+                valid.push(ImportRow {
+                    row,
+                    device,
+                    labels: record.labels,
+                });
+            }
+            Err(e) => errors.push(format!("row {}: {}", row, e)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Validated {
+            rows: valid,
+            skipped,
+        })
+    } else {
+        for error in &errors {
+            println!("{}", error);
+        }
+        Err(format!(
+            "{} invalid row(s) in {}; nothing was imported",
+            errors.len(),
+            path.display()
+        )
+        .into())
+    }
+}
+
+/// Creates every device with at most `concurrency` requests in flight.
+/// Labels are looked up by name or UUID before any device is sent, and
+/// missing ones are created only with `create_missing`.
+pub async fn import<C: ConsoleApi>(
+    client: &mut C,
+    rows: Vec<ImportRow>,
+    concurrency: usize,
+    create_missing: bool,
+) -> Result<Vec<ReportRow>> {
+    let mut label_ids = HashMap::new();
+    for row in &rows {
+        for label in &row.labels {
+            if !label_ids.contains_key(label) {
+                let id = client.get_label_uuid(label, create_missing).await?;
+                label_ids.insert(label.clone(), id);
+            }
+        }
+    }
+
+    let client = &*client;
+    let label_ids = &label_ids;
+    let existing = &DeviceIndex::new();
+    let mut report: Vec<ReportRow> = stream::iter(rows)
+        .map(|row| import_row(client, row, label_ids, existing))
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    report.sort_by_key(|row| row.row);
+    Ok(report)
+}
+
+async fn import_row<C: ConsoleApi>(
+    client: &C,
+    row: ImportRow,
+    label_ids: &HashMap<String, String>,
+    existing: &DeviceIndex,
+) -> ReportRow {
+    let mut report = ReportRow {
+        row: row.row,
+        dev_eui: row.device.dev_eui().to_string(),
+        name: row.device.name().clone(),
+        status: Status::Failed,
+        device_id: None,
+        reason: None,
+    };
+
+    let created = match client.post_device(&row.device).await {
+        Ok(device) => Ok((Status::Created, device)),
+        // same credentials already registered: resolve to that device
+        Err(ConsoleError::Conflict(_)) => existing
+            .get_device(client, &(&row.device).into())
+            .await
+            .map(|device| (Status::AlreadyExists, device)),
+        Err(err) => Err(err),
+    };
+    let (status, device) = match created {
+        Ok(created) => created,
+        Err(err) => {
+            report.reason = Some(err.to_string());
+            return report;
+        }
+    };
+    report.device_id = Some(device.id().clone());
+
+    for label in &row.labels {
+        let result = match DeviceLabel::from_uuid(label_ids[label].clone()) {
+            Ok(device_label) => {
+                client
+                    .add_device_label(device.id().clone(), &device_label)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            report.reason = Some(format!("adding label {} failed: {}", label, err));
+            return report;
+        }
+    }
+
+    report.status = status;
+    report
+}
+
+/// Default report location: next to the input, e.g. `devices.report.csv`
+pub fn default_report_path(input: &Path) -> PathBuf {
+    input.with_extension("report.csv")
+}
+
+/// Writes the report as JSON or NDJSON if the extension says so, CSV
+/// otherwise, and says where
+pub fn write_report(path: &Path, report: &[ReportRow]) -> Result {
+    match Format::from_path(path) {
+        Some(Format::Json) => fs::write(path, serde_json::to_string_pretty(report)?)?,
+        Some(Format::Ndjson) => {
+            let mut contents = String::new();
+            for row in report {
+                contents.push_str(&serde_json::to_string(row)?);
+                contents.push('\n');
+            }
+            fs::write(path, contents)?
+        }
+        _ => {
+            let mut writer = csv::Writer::from_path(path)?;
+            for row in report {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+    }
+    println!("Report written to {}", path.display());
+    Ok(())
+}
+
+/// Creates the devices read from another network server, creating
+/// missing labels, then reports like `device import`
+pub async fn import_from_source<C: ConsoleApi>(
+    client: &mut C,
+    rows: Vec<ImportRow>,
+    options: &SourceImport,
+) -> Result {
+    let results = import(client, rows, options.concurrency, true).await?;
+    if let Some(report) = &options.report {
+        write_report(report, &results)?;
+    }
+    summarize(&results)
+}
+
+/// Prints how many devices were created, already existed or failed, and
+/// fails if any did
+pub fn summarize(report: &[ReportRow]) -> Result {
+    let count = |status| report.iter().filter(|row| row.status == status).count();
+    let skipped = match count(Status::Skipped) {
+        0 => String::new(),
+        skipped => format!(", {} skipped", skipped),
+    };
+    println!(
+        "{} created, {} already existed, {} failed{}",
+        count(Status::Created),
+        count(Status::AlreadyExists),
+        count(Status::Failed),
+        skipped
+    );
+    if count(Status::Failed) > 0 {
+        return Err("Some devices failed to import. See the report".into());
+    }
+    Ok(())
+}
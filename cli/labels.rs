@@ -0,0 +1,43 @@
+//! Label arguments, given by name or UUID. Commands that only read pick
+//! the first of several case variants with a warning; commands that change
+//! data refuse to guess.
+
+use super::Result;
+use helium_console::{client::Client, ConsoleError, Label};
+
+/// Label to read from. Ambiguous names resolve to the first match, see
+/// `LabelMatch::select`
+pub async fn find(client: &mut Client, label: &str) -> Result<Label> {
+    let found = client.find_labels(label).await?;
+    warn_ambiguous(label, &found);
+    match found.into_iter().next() {
+        Some(found) => Ok(found),
+        None => Err(ConsoleError::UnknownLabel(label.to_string()).into()),
+    }
+}
+
+/// Label to change or act on. Ambiguous names are an error listing the
+/// candidates
+pub async fn resolve(client: &mut Client, label: &str) -> Result<Label> {
+    match client.find_label(label).await? {
+        Some(found) => Ok(found),
+        None => Err(ConsoleError::UnknownLabel(label.to_string()).into()),
+    }
+}
+
+/// Tells the user which label was picked when a name matches more than one,
+/// e.g. "warehouse" and "Warehouse" under case-insensitive matching
+fn warn_ambiguous(requested: &str, found: &[Label]) {
+    if let [first, _, ..] = found {
+        let names: Vec<String> = found
+            .iter()
+            .map(|label| format!("\"{}\"", label.name()))
+            .collect();
+        eprintln!(
+            "warning: \"{}\" matches several labels ({}); using \"{}\"",
+            requested,
+            names.join(", "),
+            first.name()
+        );
+    }
+}
@@ -1,22 +1,62 @@
+use futures::TryStreamExt;
 use oauth2::{prelude::SecretNewType, AuthorizationCode};
-use prettytable::{cell, row, Table};
+use prettytable::{row, Table};
 use serde_derive::{Deserialize, Serialize};
-use std::{process, str::FromStr};
+use std::{fs, io, path::PathBuf, process, str::FromStr};
 use structopt::StructOpt;
 
 pub type Result<T = ()> = std::result::Result<T, Box<dyn std::error::Error>>;
-const CONF_PATH: &str = ".helium-console-config.toml";
 
+mod apply;
 mod clicmd;
 mod config;
+mod export;
+mod filter;
+mod import;
+mod labels;
+mod output;
+mod sources;
 
 use clicmd::*;
 use config::get_input;
 use helium_console::*;
+use output::Printer;
 
 /// Interact with Helium API via CLI
 #[derive(StructOpt, Debug)]
-pub enum Cli {
+pub struct Cli {
+    /// Print AppKeys and session keys in full instead of masking them
+    #[structopt(long, global = true)]
+    reveal_keys: bool,
+    /// Run all validations and lookups, but print the POST, PUT and DELETE
+    /// requests instead of sending them
+    #[structopt(long, global = true)]
+    dry_run: bool,
+    /// Config profile to use; defaults to $HELIUM_CONSOLE_PROFILE, then the
+    /// file's default_profile, then "default"
+    #[structopt(long, global = true)]
+    profile: Option<String>,
+    /// API key; overrides $HELIUM_CONSOLE_API_KEY and the profile
+    #[structopt(long, global = true)]
+    api_key: Option<String>,
+    /// Console URL; overrides $HELIUM_CONSOLE_BASE_URL and the profile
+    #[structopt(long, global = true)]
+    base_url: Option<String>,
+    /// Request timeout in seconds; overrides $HELIUM_CONSOLE_TIMEOUT and the profile
+    #[structopt(long, global = true)]
+    timeout: Option<u64>,
+    /// table, json, yaml or debug; applies to device and label listings
+    /// and records. Each command has its own default. JSON listings are one
+    /// document such as {"devices": [...]}, except `devices all`, which
+    /// prints one record per line
+    #[structopt(long, global = true)]
+    output: Option<output::Format>,
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
     /// List, create, and delete devices
     Device {
         #[structopt(subcommand)]
@@ -32,11 +72,34 @@ pub enum Cli {
         #[structopt(subcommand)]
         cmd: LabelCmd,
     },
+    /// Set up and inspect config profiles
+    Config {
+        #[structopt(subcommand)]
+        cmd: ConfigCmd,
+    },
+    /// Reconcile the organization with a YAML manifest of labels and devices
+    /// Prints a plan of the changes and applies it after confirmation
+    Apply {
+        /// Manifest listing labels and devices
+        #[structopt(short, long, parse(from_os_str))]
+        file: PathBuf,
+        /// Also delete devices and labels that are not in the manifest
+        #[structopt(long)]
+        prune: bool,
+        /// Apply the plan without asking for confirmation
+        #[structopt(short, long)]
+        yes: bool,
+    },
     /// Import devices from TTN to Helium
     Ttn {
         #[structopt(subcommand)]
         cmd: TtnCmd,
     },
+    /// Import devices from ChirpStack to Helium
+    Chirpstack {
+        #[structopt(subcommand)]
+        cmd: ChirpstackCmd,
+    },
 }
 
 #[tokio::main]
@@ -51,48 +114,108 @@ async fn main() -> Result {
 }
 
 async fn run(cli: Cli) -> Result {
-    match cli {
-        Cli::Device { cmd } => {
-            let config = config::load(CONF_PATH)?;
-            let client = client::Client::new(config)?;
+    let printer = Printer::new(cli.reveal_keys, cli.output);
+    let dry_run = if cli.dry_run {
+        println!("Dry run: no changes will be made");
+        Some(DryRun::new())
+    } else {
+        None
+    };
+    let context = Context {
+        profile: cli.profile,
+        overrides: config::Overrides {
+            api_key: cli.api_key,
+            base_url: cli.base_url,
+            timeout: cli.timeout,
+        },
+        dry_run: dry_run.clone(),
+    };
+    match cli.cmd {
+        Command::Device { cmd } => {
+            let mut client = context.client()?;
 
             match cmd {
-                DeviceCmd::List { oneline } => {
+                DeviceCmd::List { oneline, filter } => {
+                    if oneline && printer.format_or(output::Format::Json) != output::Format::Json {
+                        return Err(
+                            "--oneline cannot be combined with --output other than json".into()
+                        );
+                    }
                     #[derive(Deserialize, Serialize)]
                     struct Output {
                         devices: Vec<Device>,
                     }
+                    let label = match &filter.label {
+                        Some(label) => Some(labels::find(&mut client, label).await?),
+                        None => None,
+                    };
+                    let devices = client.get_detailed_devices().await?;
                     let output = Output {
-                        devices: client.get_devices().await?,
+                        devices: filter
+                            .apply(devices, label.as_ref())
+                            .iter()
+                            .map(Device::from)
+                            .collect(),
                     };
                     if oneline {
-                        println!("{}", serde_json::to_string(&output)?);
+                        println!("{}", printer.json(&output)?);
                     } else {
-                        println!("{{ \"devices\":");
-                        println!("[");
-                        let len = output.devices.len();
-                        for (index, device) in output.devices.iter().enumerate() {
-                            if index + 1 != len {
-                                println!("{},", serde_json::to_string(&device)?);
-                            } else {
-                                println!("{}", serde_json::to_string(&device)?);
-                            }
-                        }
-                        println!("]");
-                        println!("}}");
+                        printer.records("devices", &output.devices, output::Format::Json)?;
                     }
                 }
                 DeviceCmd::Get {
                     app_eui,
                     app_key,
                     dev_eui,
+                    select,
                 } => {
-                    let request = GetDevice::from_user_input(app_eui, app_key, dev_eui)?;
-                    println!("{:#?}", client.get_device(&request).await?)
+                    let selector =
+                        device_selector(&mut client, app_eui, app_key, dev_eui, select).await?;
+                    printer.record(&client.find_device(&selector).await?)?
                 }
                 DeviceCmd::GetById { id } => {
                     validate_uuid_input(&id)?;
-                    println!("{:#?}", client.get_device_by_id(&id).await?)
+                    printer.record(&client.get_device_by_id(&id).await?)?
+                }
+                DeviceCmd::Update {
+                    id,
+                    name,
+                    active,
+                    adr_allowed,
+                    cf_list_enabled,
+                    config_profile_id,
+                    rx_delay,
+                } => {
+                    validate_uuid_input(&id)?;
+                    let mut update = DeviceUpdate::new();
+                    if let Some(name) = name {
+                        update = update.name(name);
+                    }
+                    if let Some(active) = active {
+                        update = update.active(active);
+                    }
+                    if let Some(adr_allowed) = adr_allowed {
+                        update = update.adr_allowed(adr_allowed);
+                    }
+                    if let Some(cf_list_enabled) = cf_list_enabled {
+                        update = update.cf_list_enabled(cf_list_enabled);
+                    }
+                    if let Some(config_profile_id) = config_profile_id {
+                        update = update.config_profile_id(config_profile_id)?;
+                    }
+                    if let Some(rx_delay) = rx_delay {
+                        update = update.rx_delay(rx_delay);
+                    }
+                    if update.is_empty() {
+                        println!("Nothing to update. Provide at least one field to change");
+                    } else {
+                        client.update_device(&id, &update).await?;
+                        // under --dry-run nothing changed, so there is nothing to show
+                        if dry_run.is_none() {
+                            println!("Device update successful");
+                            printer.record(&client.get_device_by_id(&id).await?)?
+                        }
+                    }
                 }
                 DeviceCmd::Create {
                     app_eui,
@@ -100,320 +223,811 @@ async fn run(cli: Cli) -> Result {
                     dev_eui,
                     name,
                 } => {
-                    let new_device = NewDevice::from_user_input(app_eui, app_key, dev_eui, name)?;
-                    println!("{:#?}", client.post_device(&new_device).await?);
+                    let new_device = NewDevice::new(app_eui, app_key, dev_eui, name);
+                    printer.record(&client.post_device(&new_device).await?)?;
                 }
                 DeviceCmd::CreateByAppEui { app_eui, mut name } => {
                     let app_key = Device::random_app_key();
                     let dev_eui = Device::random_dev_eui();
                     name.push('_');
-                    name.push_str(&dev_eui[11..].to_uppercase());
-                    let new_device = NewDevice::from_user_input(app_eui, app_key, dev_eui, name)?;
-                    println!("{:#?}", client.post_device(&new_device).await?);
+                    name.push_str(&dev_eui.to_string()[11..]);
+                    let new_device = NewDevice::new(app_eui, app_key, dev_eui, name);
+                    printer.record(&client.post_device(&new_device).await?)?;
                 }
                 DeviceCmd::Delete {
                     app_eui,
                     app_key,
                     dev_eui,
+                    select,
                 } => {
-                    let request = GetDevice::from_user_input(app_eui, app_key, dev_eui)?;
-                    let device = client.get_device(&request).await?;
-                    client.delete_device(device.id()).await?;
+                    let selector =
+                        device_selector(&mut client, app_eui, app_key, dev_eui, select).await?;
+                    let device = client.find_device(&selector).await?;
+                    delete_device(&client, device.id()).await?;
                 }
                 DeviceCmd::DeleteById { id } => {
                     validate_uuid_input(&id)?;
-                    client.delete_device(&id).await?;
+                    delete_device(&client, &id).await?;
+                }
+                DeviceCmd::Import {
+                    file,
+                    format,
+                    app_eui,
+                    label_with_app,
+                    concurrency,
+                    report,
+                    create_missing,
+                } => {
+                    let format = match format.or_else(|| import::Format::from_path(&file)) {
+                        Some(format) => format,
+                        None => {
+                            return Err(
+                                "Cannot tell the file format from its extension. Use --format"
+                                    .into(),
+                            )
+                        }
+                    };
+                    let defaults = sources::Defaults {
+                        app_eui,
+                        label_with_app,
+                    };
+                    let validated = import::validate(&file, format, &defaults)?;
+                    let mut results =
+                        import::import(&mut client, validated.rows, concurrency, create_missing)
+                            .await?;
+                    results.extend(validated.skipped);
+                    results.sort_by_key(|result| result.row);
+
+                    let report = report.unwrap_or_else(|| import::default_report_path(&file));
+                    import::write_report(&report, &results)?;
+                    import::summarize(&results)?;
                 }
-                DeviceCmd::AddLabel { device, label } => {
-                    let device_label = DeviceLabel::from_uuid(label)?;
+                DeviceCmd::Export {
+                    format,
+                    columns,
+                    file,
+                } => {
+                    let columns = columns.unwrap_or_default();
+                    // before the file is created, so a refused export leaves it alone
+                    columns.check(&printer)?;
+                    match file {
+                        Some(file) => {
+                            let mut out = fs::File::create(&file)?;
+                            export::export(&mut client, printer, format, columns, &mut out).await?;
+                        }
+                        None => {
+                            let stdout = io::stdout();
+                            let mut out = stdout.lock();
+                            export::export(&mut client, printer, format, columns, &mut out).await?;
+                        }
+                    }
+                }
+                DeviceCmd::AddLabel {
+                    device,
+                    label,
+                    create_missing,
+                } => {
+                    let label_uuid = match client.get_label_uuid(&label, create_missing).await {
+                        Ok(label_uuid) => label_uuid,
+                        Err(ConsoleError::UnknownLabel(_)) => {
+                            return Err(format!(
+                                "No label named \"{}\". Pass --create-missing to create it",
+                                label
+                            )
+                            .into())
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+                    let device_label = DeviceLabel::from_uuid(label_uuid)?;
                     client.add_device_label(device, &device_label).await?;
+                    println!("Device label added");
                 }
                 DeviceCmd::RemoveLabel { device, label } => {
-                    let device_label = DeviceLabel::from_uuid(label)?;
-                    client.remove_device_label(device, &device_label).await?;
+                    let label_uuid = client.get_label_uuid(&label, false).await?;
+                    let device_label = DeviceLabel::from_uuid(label_uuid)?;
+                    match client.remove_device_label(device, &device_label).await {
+                        Ok(()) => println!("Device label removed"),
+                        Err(ConsoleError::NotFound(_)) => {
+                            println!("Device label not found. Delete failed.")
+                        }
+                        Err(err) => return Err(err.into()),
+                    }
                 }
             }
         }
-        Cli::Devices { cmd } => {
-            let config = config::load(CONF_PATH)?;
-            let client = client::Client::new(config)?;
+        Command::Devices { cmd } => {
+            let mut client = context.client()?;
             match cmd {
-                DevicesCmd::All => {
-                    #[derive(Deserialize, Serialize)]
-                    struct Output {
-                        devices: Vec<DetailedDevice>,
-                    }
-                    let output = Output {
-                        devices: client.get_detailed_devices().await?,
+                DevicesCmd::All { filter } => {
+                    let label = match &filter.label {
+                        Some(label) => Some(labels::find(&mut client, label).await?),
+                        None => None,
                     };
-                    println!("[");
-                    let len = output.devices.len();
-                    for (index, device) in output.devices.iter().enumerate() {
-                        if index + 1 != len {
-                            println!("{},", serde_json::to_string(&device)?);
+                    if filter.streams()
+                        && printer.format_or(output::Format::Json) == output::Format::Json
+                    {
+                        // one JSON record per line, printed as pages arrive
+                        let mut devices = client.stream_detailed_devices();
+                        let mut printed = 0;
+                        while printed < filter.limit() {
+                            match devices.try_next().await? {
+                                Some(device) if filter.matches(&device, label.as_ref()) => {
+                                    println!("{}", printer.json(&device)?);
+                                    printed += 1;
+                                }
+                                Some(_) => (),
+                                None => break,
+                            }
+                        }
+                    } else {
+                        let devices = client.get_detailed_devices().await?;
+                        let devices = filter.apply(devices, label.as_ref());
+                        if printer.format_or(output::Format::Json) == output::Format::Json {
+                            for device in devices {
+                                println!("{}", printer.json(&device)?);
+                            }
                         } else {
-                            println!("{}", serde_json::to_string(&device)?);
+                            printer.records("devices", &devices, output::Format::Json)?;
                         }
                     }
-                    println!("]");
                 }
             }
         }
-        Cli::Label { cmd } => {
-            let config = config::load(CONF_PATH)?;
-            let mut client = client::Client::new(config)?;
+        Command::Label { cmd } => {
+            let mut client = context.client()?;
 
             match cmd {
-                LabelCmd::List => println!("{:#?}", client.get_labels().await?),
+                LabelCmd::List => {
+                    printer.records("labels", &client.get_labels().await?, output::Format::Debug)?
+                }
                 LabelCmd::Create { name } => {
                     let request = NewLabel::from_string(&name);
-                    println!("{:#?}", client.post_label(&request).await?);
+                    printer.record(&client.post_label(&request).await?)?;
                 }
-                LabelCmd::DeleteById { id } => {
-                    validate_uuid_input(&id)?;
-                    client.delete_label(&id).await?;
+                LabelCmd::Get { label } => {
+                    printer.record(&labels::find(&mut client, &label).await?)?
+                }
+                LabelCmd::Delete { label } => match client.find_label(&label).await? {
+                    Some(label) => match client.delete_label(label.id()).await {
+                        Ok(()) => println!("Label delete successful"),
+                        Err(ConsoleError::NotFound(_)) => {
+                            println!("Label not found. Delete failed.")
+                        }
+                        Err(err) => return Err(err.into()),
+                    },
+                    None => println!("Label not found. Delete failed."),
+                },
+                LabelCmd::Rename { label, name } => {
+                    let label = labels::resolve(&mut client, &label).await?;
+                    client.rename_label(label.id(), &name).await?;
+                    println!("Label renamed");
+                }
+                LabelCmd::Devices { label } => {
+                    let label = labels::find(&mut client, &label).await?;
+                    let mut devices = client.stream_detailed_devices();
+                    while let Some(device) = devices.try_next().await? {
+                        if device.labels().contains(label.id()) {
+                            println!("{}", printer.json(&device)?);
+                        }
+                    }
+                }
+            }
+        }
+        Command::Config { cmd } => {
+            let profile = context.profile.as_deref();
+            match cmd {
+                ConfigCmd::Init { force } => {
+                    config::init(profile, &context.overrides, force)?;
+                }
+                ConfigCmd::Show => {
+                    config::show(profile, &context.overrides, printer.reveals_keys())?
+                }
+                ConfigCmd::Set { field, value } => config::set(profile, &field, &value)?,
+                ConfigCmd::Validate => config::validate()?,
+                ConfigCmd::Whoami => {
+                    let client = context.client()?;
+                    let organization = client.get_organization().await?;
+                    println!(
+                        "Organization: {} ({})",
+                        organization.name(),
+                        organization.id()
+                    );
+                }
+            }
+        }
+        Command::Apply { file, prune, yes } => {
+            let manifest = apply::Manifest::load(&file)?;
+            let mut client = context.client()?;
+
+            let plan = apply::Plan::compute(&mut client, &manifest, prune).await?;
+            plan.print();
+            if !plan.is_empty() {
+                let confirmed = yes || dry_run.is_some() || {
+                    let answer = get_input("Apply these changes? Please type y or n");
+                    matches!(answer.as_str(), "Y" | "y" | "YES" | "Yes" | "yes")
+                };
+                if confirmed {
+                    plan.execute(&mut client).await?;
+                    println!("Apply complete");
+                } else {
+                    println!("Apply cancelled");
                 }
             }
         }
-        Cli::Ttn { cmd } => match cmd {
-            TtnCmd::Import => {
-                ttn_import().await?;
+        Command::Ttn { cmd } => match cmd {
+            TtnCmd::Import(options) => {
+                ttn_import(printer, &context, options).await?;
+            }
+            TtnCmd::ImportV3(options) => {
+                ttn_v3_import(&context, options).await?;
+            }
+        },
+        Command::Chirpstack { cmd } => match cmd {
+            ChirpstackCmd::Import(options) => {
+                chirpstack_import(&context, options).await?;
             }
         },
     }
+
+    if let Some(dry_run) = dry_run {
+        let requests = dry_run.take();
+        if requests.is_empty() {
+            println!("Dry run: no requests would have been sent");
+        } else {
+            println!("Dry run: the following requests were not sent");
+            for request in &requests {
+                printer.display(request);
+            }
+        }
+    }
     Ok(())
 }
 
-async fn ttn_import() -> Result {
-    println!("Generate a ttnctl access code at https://account.thethingsnetwork.org/");
-    let mut ttn_client = ttn::Client::new()?;
+/// Global options that decide how commands reach the Console
+struct Context {
+    profile: Option<String>,
+    overrides: config::Overrides,
+    dry_run: Option<DryRun>,
+}
 
-    let access_code = AuthorizationCode::new(get_input("Provide a single use ttnctl access code"));
-    let account_token = ttn_client.get_account_token(access_code)?;
+impl Context {
+    /// Builds a client from the selected profile that, under --dry-run,
+    /// records mutating requests instead of sending them
+    fn client(&self) -> Result<client::Client> {
+        let client = client::Client::new(config::load(self.profile.as_deref(), &self.overrides)?)?;
+        Ok(match &self.dry_run {
+            Some(dry_run) => client.with_dry_run(dry_run.clone()),
+            None => client,
+        })
+    }
+}
 
-    let apps = ttn_client.get_apps(&account_token).await?;
+/// Selector for `device get` and `device delete`: either all three
+/// positional credentials or exactly one of the selector flags
+async fn device_selector(
+    client: &mut client::Client,
+    app_eui: Option<AppEui>,
+    app_key: Option<AppKey>,
+    dev_eui: Option<DevEui>,
+    select: SelectDevice,
+) -> Result<DeviceSelector> {
+    let positional = app_eui.is_some() || app_key.is_some() || dev_eui.is_some();
+    let flag = select.by_dev_eui.is_some() || select.by_name.is_some() || select.by_label.is_some();
+    if positional && flag {
+        return Err("Give either app_eui, app_key and dev_eui or a selector flag, not both".into());
+    }
+    if let (Some(app_eui), Some(app_key), Some(dev_eui)) = (app_eui, app_key, dev_eui) {
+        return Ok(DeviceSelector::Credentials(GetDevice::new(
+            app_eui, app_key, dev_eui,
+        )));
+    }
+    if let Some(dev_eui) = select.by_dev_eui {
+        return Ok(DeviceSelector::DevEui(dev_eui));
+    }
+    if let Some(name) = select.by_name {
+        return Ok(DeviceSelector::Name(name));
+    }
+    if let Some(label) = select.by_label {
+        return Ok(DeviceSelector::Label(
+            labels::resolve(client, &label).await?,
+        ));
+    }
+    Err(
+        "Select the device with app_eui, app_key and dev_eui, or with --dev-eui, --name or --label"
+            .into(),
+    )
+}
 
-    let mut table = Table::new();
-    table.add_row(row!["Index", "Name", "ID"]);
-    for (index, app) in apps.iter().enumerate() {
-        table.add_row(row![index + 1, app.name, app.id,]);
+async fn delete_device(client: &client::Client, id: &str) -> Result {
+    match client.delete_device(id).await {
+        Ok(()) => println!("Device delete successful"),
+        Err(ConsoleError::NotFound(_)) => println!("Device not found. Delete failed."),
+        Err(err) => return Err(err.into()),
     }
+    Ok(())
+}
 
-    table.printstd();
+async fn ttn_import(printer: Printer, context: &Context, options: TtnImport) -> Result {
+    if options.yes {
+        if options.access_code.is_none() {
+            return Err("--yes needs --access-code, since nothing is read from stdin".into());
+        }
+        if options.apps.is_empty() && !options.all_apps {
+            return Err("--yes needs --apps or --all-apps".into());
+        }
+        if options.delete_from_ttn == Some(DeleteFromTtn::Prompt) {
+            return Err("--delete-from-ttn=prompt cannot be combined with --yes".into());
+        }
+    }
 
-    let index_input =
-        get_input("Import which application? Type 0 for ALL (no more than 10 at a time supported)");
+    let mut ttn_client = ttn::Client::new()?;
+    if let Some(account_url) = &options.account_url {
+        ttn_client = ttn_client.with_account_url(account_url);
+    }
+    if !options.handler_urls.is_empty() {
+        ttn_client = ttn_client.with_handler_urls(&options.handler_urls);
+    }
+    if let Some(dry_run) = &context.dry_run {
+        ttn_client = ttn_client.with_dry_run(dry_run.clone());
+    }
 
-    let index = get_number_from_user(index_input);
+    let access_code = match &options.access_code {
+        Some(access_code) => access_code.clone(),
+        None => {
+            println!("Generate a ttnctl access code at https://account.thethingsnetwork.org/");
+            get_input("Provide a single use ttnctl access code")
+        }
+    };
+    let mut account_token = ttn_client.get_account_token(AuthorizationCode::new(access_code))?;
 
-    let token;
-    if index > apps.len() {
-        println!("There is no app with index {}", index);
-    } else {
-        let mut devices = Vec::new();
-        // 0 index is reserved to select all
-        if index == 0 {
-            // You can restrict the OAuth2 token into having access to
-            // 10 items or less. So if we want to support more than 10
-            // applications imported at a time, we will need to ask
-            // the user for a new token
-            if apps.len() > 10 {
-                panic!("Due to TTN Auth limitations, importing more than 10 apps at once not currently supported");
-            }
+    let apps = select_apps(ttn_client.get_apps(&account_token).await?, &options)?;
+    if apps.is_empty() {
+        return Ok(());
+    }
 
-            // the account token is consumed
-            token = ttn_client
-                .exchange_for_app_token(account_token, apps.clone().into_vec_string())
-                .await?;
-            for app in &apps {
-                ttn_client.get_devices(&app.id, &token).await?;
-                devices.extend(ttn_client.get_devices(&app.id, &token).await?);
+    let mut client = context.client()?;
+    let existing = DeviceIndex::new();
+    // asked at the first batch with devices
+    let mut answers = None;
+
+    // TTN restricts a token to 10 applications, so larger selections are
+    // imported in batches with one restricted token each. Each batch is
+    // imported before the next token is exchanged, so a run stopped for a
+    // new access code can go on with the applications left.
+    let batches: Vec<&[ttn::App]> = apps.chunks(ttn::MAX_APPS_PER_TOKEN).collect();
+    for (batch, batch_apps) in batches.iter().enumerate() {
+        let app_ids = batch_apps.to_vec().into_vec_string();
+        if batches.len() > 1 {
+            println!(
+                "Batch {} of {}: {}",
+                batch + 1,
+                batches.len(),
+                app_ids.join(", ")
+            );
+        }
+        let token = loop {
+            match ttn_client
+                .exchange_for_app_token(&account_token, app_ids.clone())
+                .await
+            {
+                Ok(token) => break token,
+                Err(err) => match err.downcast_ref::<ttn::Error>() {
+                    Some(ttn::Error::TokenConsumed) if !options.yes => {
+                        println!(
+                            "TTN needs a new access code for batch {} of {} ({})",
+                            batch + 1,
+                            batches.len(),
+                            app_ids.join(", ")
+                        );
+                        let access_code =
+                            get_input("Provide another single use ttnctl access code");
+                        account_token =
+                            ttn_client.get_account_token(AuthorizationCode::new(access_code))?;
+                    }
+                    Some(ttn::Error::TokenConsumed) => {
+                        let remaining: Vec<&str> = apps[batch * ttn::MAX_APPS_PER_TOKEN..]
+                            .iter()
+                            .map(|app| app.id.as_str())
+                            .collect();
+                        return Err(format!(
+                            "TTN needs a new access code for batch {} of {}. The batches before \
+                             it were imported; run again with --apps {}",
+                            batch + 1,
+                            batches.len(),
+                            remaining.join(",")
+                        )
+                        .into());
+                    }
+                    _ => return Err(err),
+                },
             }
-        // you can select one by one
-        } else {
-            let app = apps[index - 1].clone();
-            // the account token is consumed
-            token = ttn_client
-                .exchange_for_app_token(account_token, vec![app.id.clone()])
-                .await?;
+        };
+
+        let mut devices = Vec::new();
+        for app in batch_apps.iter() {
             devices.extend(ttn_client.get_devices(&app.id, &token).await?);
         }
+        if devices.is_empty() {
+            continue;
+        }
+        let answers = answers
+            .get_or_insert_with(|| ask_import_questions(&options, devices.len(), batches.len()));
+        ttn_import_batch(
+            printer,
+            &mut client,
+            &ttn_client,
+            &existing,
+            devices,
+            &token,
+            answers,
+        )
+        .await?;
+    }
+
+    if answers.is_none() {
+        println!("No devices to import");
+    }
+    Ok(())
+}
+
+/// Answers to the questions asked before TTN devices are imported
+struct ImportAnswers {
+    input_all: UserResponse,
+    do_label: UserResponse,
+    do_delete: UserResponse,
+}
+
+/// `devices` is the number of devices in the first batch
+fn ask_import_questions(options: &TtnImport, devices: usize, batches: usize) -> ImportAnswers {
+    // First question: import all devices or one by one?
+    let input_all = if options.yes {
+        UserResponse::Yes
+    } else {
+        let later_batches = if batches > 1 {
+            " of this batch and the batches after it"
+        } else {
+            ""
+        };
+        let first_answer =
+        get_input(format!("Import all {} devices{} at once? Otherwise, proceed with device by device import. Please type y or n", devices, later_batches).as_str());
+        yes_or_no(first_answer, Some("Import ALL devices? Please type y or n"))
+    };
+
+    // Second question: apply label to all? don't apply label to all? or one by one?
+    let do_label = if options.label_with_app_id {
+        UserResponse::Yes
+    } else if options.yes {
+        UserResponse::No
+    } else {
+        let first_answer =
+            get_input("Apply TTN application ID as Label to ALL devices? Please type y or n");
+        let label_all = yes_or_no(first_answer, Some(" Please type y or n"));
 
-        let config = config::load(CONF_PATH)?;
-        let mut client = client::Client::new(config)?;
+        if UserResponse::No == label_all {
+            let first_answer =
+            get_input("Skip applying TTN application ID as Label to ALL devices? Otherwise, proceed with device by device approval. Please type y or n");
+            let dont_label_all = yes_or_no(first_answer, Some(" Please type y or n"));
 
-        // First question: import all devices or one by one?
-        if !devices.is_empty() {
-            println!("App has no devices. Moving to next app");
+            match dont_label_all {
+                UserResponse::No => UserResponse::Maybe,
+                UserResponse::Yes => UserResponse::No,
+                UserResponse::Maybe => panic!("maybe not valid here"),
+            }
         } else {
+            UserResponse::Yes
+        }
+    };
+
+    // Third question: delete all? don't apply delete all? or one by one?
+    let do_delete = match options.delete_from_ttn {
+        Some(DeleteFromTtn::Always) => UserResponse::Yes,
+        Some(DeleteFromTtn::Never) => UserResponse::No,
+        Some(DeleteFromTtn::Prompt) => UserResponse::Maybe,
+        None if options.yes => UserResponse::No,
+        None => {
             let first_answer =
-            get_input(format!("Import all {} devices at once? Otherwise, proceed with device by device import. Please type y or n", devices.len()).as_str());
-            let input_all = yes_or_no(first_answer, Some("Import ALL devices? Please type y or n"));
-
-            // Second question: apply label to all? don't apply label to all? or one by one?
-            let do_label = {
-                let first_answer = get_input(
-                    "Apply TTN application ID as Label to ALL devices? Please type y or n",
-                );
-                let label_all = yes_or_no(first_answer, Some(" Please type y or n"));
-
-                if UserResponse::No == label_all {
-                    let first_answer =
-                    get_input("Skip applying TTN application ID as Label to ALL devices? Otherwise, proceed with device by device approval. Please type y or n");
-                    let dont_label_all = yes_or_no(first_answer, Some(" Please type y or n"));
-
-                    match dont_label_all {
-                        UserResponse::No => UserResponse::Maybe,
-                        UserResponse::Yes => UserResponse::No,
-                        UserResponse::Maybe => panic!("maybe not valid here"),
-                    }
-                } else {
-                    UserResponse::Yes
-                }
-            };
+            get_input("Delete ALL devices from TTN? Neglecting to do so will cause a race condition on Join. Please type y or n");
+            let label_all = yes_or_no(first_answer, Some(" Please type y or n"));
 
-            // Third question: delete all? don't apply delete all? or one by one?
-            let do_delete = {
+            if UserResponse::No == label_all {
                 let first_answer =
-                get_input("Delete ALL devices from TTN? Neglecting to do so will cause a race condition on Join. Please type y or n");
-                let label_all = yes_or_no(first_answer, Some(" Please type y or n"));
-
-                if UserResponse::No == label_all {
-                    let first_answer =
-                    get_input("Skip deleting ALL devices? Otherwise, proceed with device by device delete prompts. Please type y or n");
-                    let dont_label_all = yes_or_no(first_answer, Some(" Please type y or n"));
-
-                    match dont_label_all {
-                        UserResponse::No => UserResponse::Maybe,
-                        UserResponse::Yes => UserResponse::No,
-                        UserResponse::Maybe => panic!("maybe not valid here"),
-                    }
-                } else {
-                    UserResponse::Yes
+                get_input("Skip deleting ALL devices? Otherwise, proceed with device by device delete prompts. Please type y or n");
+                let dont_label_all = yes_or_no(first_answer, Some(" Please type y or n"));
+
+                match dont_label_all {
+                    UserResponse::No => UserResponse::Maybe,
+                    UserResponse::Yes => UserResponse::No,
+                    UserResponse::Maybe => panic!("maybe not valid here"),
+                }
+            } else {
+                UserResponse::Yes
+            }
+        }
+    };
+
+    ImportAnswers {
+        input_all,
+        do_label,
+        do_delete,
+    }
+}
+
+/// Imports the devices of one batch, which `token` can delete from TTN
+async fn ttn_import_batch(
+    printer: Printer,
+    client: &mut client::Client,
+    ttn_client: &ttn::Client,
+    existing: &DeviceIndex,
+    devices: Vec<ttn::TtnDevice>,
+    token: &str,
+    answers: &ImportAnswers,
+) -> Result {
+    for ttn_device in devices {
+        if ttn_device.appkey().is_none() {
+            if !ttn_device.appskey().expose_secret().is_empty() {
+                println!(
+                    "{}",
+                    format!(
+                        "WARNING: ABP device not supported {:?}",
+                        ttn_device.get_simple_string()
+                    )
+                    .as_str()
+                )
+            }
+        } else {
+            // if user elected to import all
+            // create_device will always be Yes
+            let create_device = match answers.input_all {
+                UserResponse::Yes => UserResponse::Yes,
+                UserResponse::No => {
+                    let first_answer = get_input(
+                        format!("Import device? {:?}", ttn_device.get_simple_string()).as_str(),
+                    );
+                    yes_or_no(first_answer, Some("Please type y or n"))
+                }
+                UserResponse::Maybe => {
+                    panic!("User reponse for create device must be yes or no")
                 }
             };
 
-            for ttn_device in devices {
-                if ttn_device.appkey() == "" {
-                    if ttn_device.appskey() != "" {
-                        println!(
-                            "{}",
-                            format!(
-                                "WARNING: ABP device not supported {:?}",
-                                ttn_device.get_simple_string()
-                            )
-                            .as_str()
-                        )
-                    }
-                } else {
-                    // if user elected to import all
-                    // create_device will always be Yes
-                    let create_device = match input_all {
-                        UserResponse::Yes => UserResponse::Yes,
-                        UserResponse::No => {
-                            let first_answer = get_input(
-                                format!("Import device? {:?}", ttn_device.get_simple_string())
-                                    .as_str(),
-                            );
-                            yes_or_no(first_answer, Some("Please type y or n"))
+            match create_device {
+                UserResponse::Yes => {
+                    let appid = ttn_device.appid().clone();
+                    let request = ttn_device.derive_new_device_request()?;
+
+                    let device = match client.post_device(&request).await {
+                        Ok(device) => {
+                            println!("Successly Created");
+                            printer.debug(&device);
+                            Some(device)
                         }
-                        UserResponse::Maybe => {
-                            panic!("User reponse for create device must be yes or no")
+                        Err(err) => {
+                            println!("{}", err);
+                            match err {
+                                ConsoleError::Conflict(_) => {
+                                    Some(existing.get_device(client, &(&request).into()).await?)
+                                }
+                                _ => None,
+                            }
                         }
                     };
 
-                    match create_device {
-                        UserResponse::Yes => {
-                            let appid = ttn_device.appid().clone();
-                            let request = ttn_device.derive_new_device_request()?;
-
-                            let device = match client.post_device(&request).await {
-                                Ok(device) => {
-                                    println!("Successly Created {:?}", device);
-                                    Some(device)
-                                }
-                                Err(err) => {
-                                    println!("{}", err);
-                                    if let Some(error) = err.downcast_ref::<Error>() {
-                                        match error {
-                                            Error::NewDevice422 => {
-                                                let request = GetDevice::from_user_input(
-                                                    request.app_eui().clone(),
-                                                    request.app_key().clone(),
-                                                    request.dev_eui().clone(),
-                                                )?;
-                                                Some(client.get_device(&request).await?)
-                                            }
-                                            _ => None,
-                                        }
-                                    } else {
-                                        None
-                                    }
-                                }
-                            };
-
-                            if let Some(device) = &device {
-                                let confirm = match do_label {
+                    if let Some(device) = &device {
+                        let confirm = match answers.do_label {
+                            UserResponse::Yes => true,
+                            UserResponse::No => false,
+                            UserResponse::Maybe => {
+                                let first_answer = get_input("Add label to device?");
+                                let answer = yes_or_no(first_answer, Some("Please type y or n"));
+                                match answer {
                                     UserResponse::Yes => true,
                                     UserResponse::No => false,
                                     UserResponse::Maybe => {
-                                        let first_answer = get_input("Add label to device?");
-                                        let answer =
-                                            yes_or_no(first_answer, Some("Please type y or n"));
-                                        match answer {
-                                            UserResponse::Yes => true,
-                                            UserResponse::No => false,
-                                            UserResponse::Maybe => {
-                                                panic!("Maybe should not occur here")
-                                            }
-                                        }
+                                        panic!("Maybe should not occur here")
                                     }
-                                };
-                                if confirm {
-                                    println!("Adding label to device {}", appid);
-                                    let label_uuid = client.get_label_uuid(&appid).await?;
-                                    let device_label = DeviceLabel::from_uuid(label_uuid)?;
-                                    client
-                                        .add_device_label(device.id().to_string(), &device_label)
-                                        .await?;
                                 }
                             }
+                        };
+                        if confirm {
+                            println!("Adding label to device {}", appid);
+                            let label_uuid = client.get_label_uuid(&appid, true).await?;
+                            let device_label = DeviceLabel::from_uuid(label_uuid)?;
+                            client
+                                .add_device_label(device.id().to_string(), &device_label)
+                                .await?;
+                        }
+                    }
 
-                            let confirm = match do_delete {
+                    let confirm = match answers.do_delete {
+                        UserResponse::No => false,
+                        // a device that is not in the Console must stay in TTN
+                        _ if device.is_none() => {
+                            println!("Keeping the device in TTN, since it was not imported");
+                            false
+                        }
+                        UserResponse::Yes => true,
+                        UserResponse::Maybe => {
+                            let first_answer = get_input("Delete device?");
+                            let answer = yes_or_no(first_answer, Some("Please type y or n"));
+                            match answer {
                                 UserResponse::Yes => true,
                                 UserResponse::No => false,
                                 UserResponse::Maybe => {
-                                    let first_answer = get_input("Delete device?");
-                                    let answer =
-                                        yes_or_no(first_answer, Some("Please type y or n"));
-                                    match answer {
-                                        UserResponse::Yes => true,
-                                        UserResponse::No => false,
-                                        UserResponse::Maybe => {
-                                            panic!("Maybe should not occur here")
-                                        }
-                                    }
+                                    panic!("Maybe should not occur here")
                                 }
-                            };
-                            if confirm {
-                                println!("Deleting device {} from TTN", appid);
-                                ttn_client.delete_device(ttn_device, &token).await?
                             }
                         }
-                        UserResponse::No => {
-                            println!("Skipping device");
-                        }
-                        UserResponse::Maybe => {
-                            panic!("User reponse for create device must be yes or no")
-                        }
+                    };
+                    if confirm {
+                        println!("Deleting device {} from TTN", appid);
+                        ttn_client.delete_device(ttn_device, token).await?
                     }
                 }
+                UserResponse::No => {
+                    println!("Skipping device");
+                }
+                UserResponse::Maybe => {
+                    panic!("User reponse for create device must be yes or no")
+                }
             }
         }
     }
     Ok(())
 }
 
+/// Imports from The Things Stack through the same pipeline as
+/// `device import`; devices without a DevEUI or readable AppKey are skipped
+async fn ttn_v3_import(context: &Context, options: TtnImportV3) -> Result {
+    let api_key = required_flag_or_env(options.tts_api_key, "--tts-api-key", TTS_API_KEY_ENV)?;
+    let cluster_url = flag_or_env(options.cluster_url, TTS_CLUSTER_URL_ENV)
+        .unwrap_or_else(|| ttn_v3::DEFAULT_CLUSTER_URL.to_string());
+    let tts = ttn_v3::Client::new(&cluster_url, &api_key)?;
+
+    let apps = if options.all_apps {
+        tts.get_applications()
+            .await?
+            .iter()
+            .map(|app| app.id().clone())
+            .collect()
+    } else {
+        options.apps
+    };
+
+    let mut rows = Vec::new();
+    for app in &apps {
+        for device in tts.get_end_devices(app).await? {
+            let device = tts.get_root_keys(&device).await?;
+            match device.derive_new_device_request() {
+                Ok(new_device) => rows.push(import::ImportRow {
+                    row: rows.len() + 1,
+                    device: new_device,
+                    labels: if options.label_with_app_id {
+                        vec![app.clone()]
+                    } else {
+                        Vec::new()
+                    },
+                }),
+                Err(err) => println!("Skipping {}/{}: {}", app, device.device_id(), err),
+            }
+        }
+    }
+
+    import::import_from_source(&mut context.client()?, rows, &options.import).await
+}
+
+/// Imports from ChirpStack through the same pipeline as `device import`;
+/// devices without OTAA keys are skipped
+async fn chirpstack_import(context: &Context, options: ChirpstackImport) -> Result {
+    let server_url = required_flag_or_env(
+        options.server_url,
+        "--server-url",
+        CHIRPSTACK_SERVER_URL_ENV,
+    )?;
+    let api_token =
+        required_flag_or_env(options.api_token, "--api-token", CHIRPSTACK_API_TOKEN_ENV)?;
+    let chirpstack = chirpstack::Client::new(&server_url, &api_token)?;
+
+    let known = chirpstack
+        .get_applications(options.organization_id.as_deref())
+        .await?;
+    let applications: Vec<&chirpstack::Application> = if options.all_applications {
+        known.iter().collect()
+    } else {
+        let mut selected = Vec::new();
+        for id in &options.applications {
+            match known.iter().find(|app| app.id() == id) {
+                Some(app) => selected.push(app),
+                None => return Err(format!("No ChirpStack application with ID {}", id).into()),
+            }
+        }
+        selected
+    };
+
+    let mut rows = Vec::new();
+    for application in applications {
+        for device in chirpstack.get_devices(application.id()).await? {
+            let device = chirpstack.get_keys(&device).await?;
+            match device.derive_new_device_request(options.app_eui) {
+                Ok(new_device) => rows.push(import::ImportRow {
+                    row: rows.len() + 1,
+                    device: new_device,
+                    labels: match options.label_with {
+                        Some(ChirpstackLabel::Application) => vec![application.name().clone()],
+                        Some(ChirpstackLabel::DeviceProfile)
+                            if !device.device_profile_name().is_empty() =>
+                        {
+                            vec![device.device_profile_name().clone()]
+                        }
+                        _ => Vec::new(),
+                    },
+                }),
+                Err(err) => println!(
+                    "Skipping {}/{}: {}",
+                    application.name(),
+                    device.dev_eui(),
+                    err
+                ),
+            }
+        }
+    }
+
+    import::import_from_source(&mut context.client()?, rows, &options.import).await
+}
+
+/// A flag's value, or else the environment variable `env`
+fn flag_or_env(value: Option<String>, env: &str) -> Option<String> {
+    value.or_else(|| std::env::var(env).ok())
+}
+
+fn required_flag_or_env(value: Option<String>, flag: &str, env: &str) -> Result<String> {
+    flag_or_env(value, env).ok_or_else(|| format!("Pass {} or set ${}", flag, env).into())
+}
+
+/// The TTN applications to import: those given by --apps or --all-apps,
+/// or the one picked from a table
+fn select_apps(apps: Vec<ttn::App>, options: &TtnImport) -> Result<Vec<ttn::App>> {
+    if options.all_apps {
+        return Ok(apps);
+    }
+    if !options.apps.is_empty() {
+        let unknown: Vec<&String> = options
+            .apps
+            .iter()
+            .filter(|id| !apps.iter().any(|app| &app.id == *id))
+            .collect();
+        if !unknown.is_empty() {
+            let ids: Vec<&str> = unknown.iter().map(|id| id.as_str()).collect();
+            return Err(format!("No TTN application with ID {}", ids.join(", ")).into());
+        }
+        return Ok(apps
+            .into_iter()
+            .filter(|app| options.apps.contains(&app.id))
+            .collect());
+    }
+
+    let mut table = Table::new();
+    table.add_row(row!["Index", "Name", "ID"]);
+    for (index, app) in apps.iter().enumerate() {
+        table.add_row(row![index + 1, app.name, app.id,]);
+    }
+
+    table.printstd();
+
+    let index_input = get_input("Import which application? Type 0 for ALL");
+
+    let index = get_number_from_user(index_input);
+
+    // 0 index is reserved to select all
+    if index == 0 {
+        Ok(apps)
+    } else if index > apps.len() {
+        println!("There is no app with index {}", index);
+        Ok(Vec::new())
+    } else {
+        Ok(vec![apps[index - 1].clone()])
+    }
+}
+
 #[derive(PartialEq)]
 enum UserResponse {
     Yes,
@@ -422,11 +1036,7 @@ enum UserResponse {
 }
 
 fn yes_or_no(mut answer: String, repeated_prompt: Option<&str>) -> UserResponse {
-    let prompt = if let Some(prompt) = repeated_prompt {
-        prompt
-    } else {
-        ""
-    };
+    let prompt = repeated_prompt.unwrap_or_default();
     loop {
         match answer.as_str() {
             "Y" | "y" | "YES" | "Yes" | "yes" => {
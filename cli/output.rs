@@ -0,0 +1,210 @@
+use super::Result;
+use helium_console::{secret, DetailedDevice, Device, Label};
+use prettytable::{format, Cell, Row, Table};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    str::FromStr,
+};
+
+/// How records are printed, chosen with the global `--output` flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Table,
+    /// Records as the library's types serialize them. Field names are
+    /// fixed by those types rather than passed through from the server
+    Json,
+    Yaml,
+    /// Rust Debug output, as printed before `--output` existed
+    Debug,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Format, String> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "debug" => Ok(Format::Debug),
+            other => Err(format!(
+                "unknown output \"{}\"; expected table, json, yaml or debug",
+                other
+            )),
+        }
+    }
+}
+
+/// Records that can be printed as rows of a table
+pub trait Tabular {
+    const HEADERS: &'static [&'static str];
+
+    fn row(&self) -> Vec<String>;
+}
+
+impl Tabular for Device {
+    const HEADERS: &'static [&'static str] = &["ID", "NAME", "DEV EUI", "APP EUI", "APP KEY"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id().clone(),
+            self.name().clone(),
+            self.dev_eui().to_string(),
+            self.app_eui().to_string(),
+            self.app_key().to_string(),
+        ]
+    }
+}
+
+impl Tabular for DetailedDevice {
+    const HEADERS: &'static [&'static str] = &[
+        "ID",
+        "NAME",
+        "DEV EUI",
+        "APP EUI",
+        "ACTIVE",
+        "XOR FILTER",
+        "LABELS",
+        "LAST CONNECTED",
+        "DC USAGE",
+        "PACKETS",
+    ];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id().clone(),
+            self.name().clone(),
+            self.dev_eui().to_string(),
+            self.app_eui().to_string(),
+            self.active().to_string(),
+            self.in_xor_filter().to_string(),
+            self.labels().len().to_string(),
+            self.last_connected().unwrap_or("never").to_string(),
+            self.dc_usage().to_string(),
+            self.total_packets().to_string(),
+        ]
+    }
+}
+
+impl Tabular for Label {
+    const HEADERS: &'static [&'static str] = &["ID", "NAME"];
+
+    fn row(&self) -> Vec<String> {
+        vec![self.id().clone(), self.name().clone()]
+    }
+}
+
+/// Prints records to stdout, masking key material unless `--reveal-keys` was given
+#[derive(Clone, Copy, Debug)]
+pub struct Printer {
+    reveal_keys: bool,
+    format: Option<Format>,
+}
+
+impl Printer {
+    /// `format` is the `--output` flag; without it every command keeps
+    /// its own default
+    pub fn new(reveal_keys: bool, format: Option<Format>) -> Printer {
+        Printer {
+            reveal_keys,
+            format,
+        }
+    }
+
+    pub fn reveals_keys(&self) -> bool {
+        self.reveal_keys
+    }
+
+    /// The `--output` format, or `default` when none was given
+    pub fn format_or(&self, default: Format) -> Format {
+        self.format.unwrap_or(default)
+    }
+
+    fn scoped<R>(&self, f: impl FnOnce() -> R) -> R {
+        if self.reveal_keys {
+            secret::reveal(f)
+        } else {
+            f()
+        }
+    }
+
+    /// Pretty Debug output, as `{:#?}`
+    pub fn debug<T: Debug>(&self, value: &T) {
+        self.scoped(|| println!("{:#?}", value))
+    }
+
+    pub fn display<T: Display>(&self, value: &T) {
+        self.scoped(|| println!("{}", value))
+    }
+
+    /// Compact single-line JSON
+    pub fn json<T: Serialize>(&self, value: &T) -> serde_json::Result<String> {
+        serde_json::to_string(&self.value(value)?)
+    }
+
+    /// JSON value, for callers that reshape records before writing them.
+    /// Serialization keeps keys, so they are masked here.
+    pub fn value<T: Serialize>(&self, value: &T) -> serde_json::Result<serde_json::Value> {
+        let mut value = serde_json::to_value(value)?;
+        if !self.reveal_keys {
+            secret::mask(&mut value);
+        }
+        Ok(value)
+    }
+
+    /// A single record, in Debug form unless `--output` says otherwise
+    pub fn record<T: Serialize + Debug + Tabular>(&self, value: &T) -> Result {
+        self.scoped(|| {
+            match self.format_or(Format::Debug) {
+                Format::Table => print_table(std::slice::from_ref(value)),
+                Format::Json => println!("{}", serde_json::to_string_pretty(&self.value(value)?)?),
+                Format::Yaml => println!("{}", serde_yaml::to_string(&self.value(value)?)?),
+                Format::Debug => println!("{:#?}", value),
+            }
+            Ok(())
+        })
+    }
+
+    /// A list of records, in `default` form unless `--output` says otherwise.
+    /// JSON and YAML wrap the list as `{ "<key>": [...] }`.
+    pub fn records<T: Serialize + Debug + Tabular>(
+        &self,
+        key: &str,
+        values: &[T],
+        default: Format,
+    ) -> Result {
+        self.scoped(|| {
+            let wrapped = || {
+                let mut map = BTreeMap::new();
+                map.insert(key, values);
+                map
+            };
+            match self.format_or(default) {
+                Format::Table => print_table(values),
+                Format::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&self.value(&wrapped())?)?
+                ),
+                Format::Yaml => println!("{}", serde_yaml::to_string(&self.value(&wrapped())?)?),
+                Format::Debug => println!("{:#?}", values),
+            }
+            Ok(())
+        })
+    }
+}
+
+fn print_table<T: Tabular>(values: &[T]) {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(Row::new(
+        T::HEADERS.iter().map(|header| Cell::new(header)).collect(),
+    ));
+    for value in values {
+        table.add_row(Row::new(
+            value.row().iter().map(|cell| Cell::new(cell)).collect(),
+        ));
+    }
+    table.printstd();
+}
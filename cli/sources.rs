@@ -0,0 +1,424 @@
+//! Device exports of other network servers, read into import rows. Parsing
+//! never touches the network, so files can be checked before migrating.
+
+use super::{import::Row, Result};
+use helium_console::AppEui;
+use serde_json::Value;
+use std::{fs, path::Path};
+
+/// What to fill in where an export lacks it
+#[derive(Debug, Default)]
+pub struct Defaults {
+    /// AppEUI (JoinEUI) for devices whose export has none
+    pub app_eui: Option<AppEui>,
+    /// Label devices with the name of their source application
+    pub label_with_app: bool,
+}
+
+/// What a record of a file turned into
+#[derive(Debug)]
+pub enum Record {
+    /// A row to validate, or why it could not be read
+    Row(std::result::Result<Row, String>),
+    /// A device that cannot be migrated at all, such as an ABP device
+    Skipped {
+        id: String,
+        dev_eui: String,
+        reason: String,
+    },
+}
+
+/// Each record with its 1-based position in the file
+pub type Records = Vec<(usize, Record)>;
+
+/// A device pulled out of an export, before defaults are applied
+struct Exported {
+    /// Identifies the device in messages, e.g. `app/device`
+    id: String,
+    app: Option<String>,
+    app_eui: Option<String>,
+    app_key: Option<String>,
+    dev_eui: Option<String>,
+    name: Option<String>,
+}
+
+/// What a device without an AppKey means in an export
+#[derive(Clone, Copy)]
+enum MissingKey {
+    /// An ABP device, which network server exports list alongside OTAA ones
+    Skip,
+    /// A mistake in the file, as in onboarding lists of OTAA devices only
+    Invalid,
+}
+
+impl Exported {
+    fn into_record(self, defaults: &Defaults, missing_key: MissingKey) -> Record {
+        let app_key = match (self.app_key.filter(|key| !key.is_empty()), missing_key) {
+            (Some(app_key), _) => app_key,
+            (None, MissingKey::Skip) => {
+                return Record::Skipped {
+                    id: self.id,
+                    dev_eui: self.dev_eui.unwrap_or_default(),
+                    reason: "no OTAA AppKey".to_string(),
+                }
+            }
+            (None, MissingKey::Invalid) => {
+                return Record::Row(Err(format!("{} has no AppKey", self.id)))
+            }
+        };
+        let dev_eui = match self.dev_eui.filter(|eui| !eui.is_empty()) {
+            Some(dev_eui) => dev_eui,
+            None => return Record::Row(Err(format!("{} has no DevEUI", self.id))),
+        };
+        let app_eui = match self
+            .app_eui
+            .filter(|eui| !eui.is_empty())
+            .or_else(|| defaults.app_eui.map(|eui| eui.to_string()))
+        {
+            Some(app_eui) => app_eui,
+            None => return Record::Row(Err(format!("{} has no AppEUI; pass --app-eui", self.id))),
+        };
+        let labels = match self.app.filter(|app| !app.is_empty()) {
+            Some(app) if defaults.label_with_app => vec![app],
+            _ => Vec::new(),
+        };
+        Record::Row(Ok(Row {
+            name: self
+                .name
+                .filter(|name| !name.trim().is_empty())
+                .unwrap_or_else(|| dev_eui.clone()),
+            app_eui,
+            app_key,
+            dev_eui,
+            labels,
+        }))
+    }
+}
+
+/// Numbers the devices of an export, keeping records that could not be
+/// read at all as errors of their row
+fn collect(
+    exported: Vec<std::result::Result<Exported, String>>,
+    defaults: &Defaults,
+    missing_key: MissingKey,
+) -> Records {
+    exported
+        .into_iter()
+        .enumerate()
+        .map(|(index, device)| {
+            let record = match device {
+                Ok(device) => device.into_record(defaults, missing_key),
+                Err(e) => Record::Row(Err(e)),
+            };
+            (index + 1, record)
+        })
+        .collect()
+}
+
+fn string_at(value: &Value, path: &[&str]) -> Option<String> {
+    path.iter()
+        .try_fold(value, |value, key| value.get(key))?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// The device objects of a JSON export: an array, an object holding the
+/// array under `key`, or objects one after another as CLI tools print them
+fn json_devices(path: &Path, key: &str) -> Result<Vec<Value>> {
+    let contents = fs::read_to_string(path)?;
+    let mut devices = Vec::new();
+    for value in serde_json::Deserializer::from_str(&contents).into_iter::<Value>() {
+        match value? {
+            Value::Array(values) => devices.extend(values),
+            Value::Object(mut object) => match object.remove(key) {
+                Some(Value::Array(values)) => devices.extend(values),
+                _ => devices.push(Value::Object(object)),
+            },
+            other => return Err(format!("expected device objects, found {}", other).into()),
+        }
+    }
+    Ok(devices)
+}
+
+/// TTN v2 devices as listed by the handler API, `{ "devices": [...] }`,
+/// with the keys either nested under `lorawan_device` or at the top level
+pub fn ttn_v2(path: &Path, defaults: &Defaults) -> Result<Records> {
+    let exported = json_devices(path, "devices")?
+        .iter()
+        .map(|device| {
+            let field = |name: &str| {
+                string_at(device, &["lorawan_device", name]).or_else(|| string_at(device, &[name]))
+            };
+            let app = field("app_id");
+            let dev_id = field("dev_id");
+            Ok(Exported {
+                id: format!(
+                    "{}/{}",
+                    app.as_deref().unwrap_or("?"),
+                    dev_id.as_deref().unwrap_or("?")
+                ),
+                app,
+                app_eui: field("app_eui"),
+                app_key: field("app_key"),
+                dev_eui: field("dev_eui"),
+                name: dev_id,
+            })
+        })
+        .collect();
+    Ok(collect(exported, defaults, MissingKey::Skip))
+}
+
+/// The Things Stack end devices as printed by
+/// `ttn-lw-cli end-devices list` or `get` with root keys selected
+pub fn ttn_v3(path: &Path, defaults: &Defaults) -> Result<Records> {
+    let exported = json_devices(path, "end_devices")?
+        .iter()
+        .map(|device| {
+            let app = string_at(device, &["ids", "application_ids", "application_id"]);
+            let device_id = string_at(device, &["ids", "device_id"]);
+            Ok(Exported {
+                id: format!(
+                    "{}/{}",
+                    app.as_deref().unwrap_or("?"),
+                    device_id.as_deref().unwrap_or("?")
+                ),
+                app,
+                app_eui: string_at(device, &["ids", "join_eui"]),
+                app_key: string_at(device, &["root_keys", "app_key", "key"]),
+                dev_eui: string_at(device, &["ids", "dev_eui"]),
+                name: string_at(device, &["name"]).or(device_id),
+            })
+        })
+        .collect();
+    Ok(collect(exported, defaults, MissingKey::Skip))
+}
+
+/// Column lookup by header, ignoring case and punctuation so that
+/// `DevEUI`, `dev_eui` and `Dev EUI` are the same column
+struct Columns(Vec<String>);
+
+impl Columns {
+    fn new(headers: &csv::StringRecord) -> Columns {
+        Columns(headers.iter().map(normalize_header).collect())
+    }
+
+    fn get(&self, record: &csv::StringRecord, names: &[&str]) -> Option<String> {
+        names
+            .iter()
+            .filter_map(|name| self.0.iter().position(|header| header == name))
+            .filter_map(|index| record.get(index))
+            .map(str::to_string)
+            .find(|value| !value.is_empty())
+    }
+}
+
+fn normalize_header(header: &str) -> String {
+    header
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// The devices of a CSV export; malformed lines are errors of their row
+/// rather than of the whole file
+fn csv_devices(
+    path: &Path,
+    device: impl Fn(&Columns, &csv::StringRecord) -> Exported,
+) -> Result<Vec<std::result::Result<Exported, String>>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let columns = Columns::new(reader.headers()?);
+    Ok(reader
+        .records()
+        .map(|record| {
+            record
+                .map(|record| device(&columns, &record))
+                .map_err(|e| e.to_string())
+        })
+        .collect())
+}
+
+/// ChirpStack device dumps. ChirpStack keeps the AppKey of LoRaWAN 1.0.x
+/// devices in `nwk_key` and stores no JoinEUI, hence `--app-eui`.
+pub fn chirpstack(path: &Path, defaults: &Defaults) -> Result<Records> {
+    let exported = csv_devices(path, |columns, record| {
+        let dev_eui = columns.get(record, &["deveui"]);
+        Exported {
+            id: dev_eui.clone().unwrap_or_else(|| "?".to_string()),
+            app: columns.get(record, &["applicationname", "application", "applicationid"]),
+            app_eui: columns.get(record, &["joineui", "appeui"]),
+            app_key: columns.get(record, &["nwkkey", "appkey"]),
+            dev_eui,
+            name: columns.get(record, &["name", "devicename"]),
+        }
+    })?;
+    Ok(collect(exported, defaults, MissingKey::Skip))
+}
+
+/// Generic EUI/key lists such as LoRa Alliance onboarding files: DevEUI,
+/// JoinEUI (or AppEUI) and AppKey columns, optionally a name and application.
+/// These list OTAA devices only, so a row without an AppKey is an error.
+pub fn eui_csv(path: &Path, defaults: &Defaults) -> Result<Records> {
+    let exported = csv_devices(path, |columns, record| {
+        let dev_eui = columns.get(record, &["deveui"]);
+        Exported {
+            id: dev_eui.clone().unwrap_or_else(|| "?".to_string()),
+            app: columns.get(record, &["application", "applicationname", "app"]),
+            app_eui: columns.get(record, &["joineui", "appeui"]),
+            app_key: columns.get(record, &["appkey"]),
+            dev_eui,
+            name: columns.get(record, &["name", "devicename"]),
+        }
+    })?;
+    Ok(collect(exported, defaults, MissingKey::Invalid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    const APP_EUI: &str = "70B3D57ED0000001";
+    const APP_KEY: &str = "2B7E151628AED2A6ABF7158809CF4F3C";
+    const DEV_EUI: &str = "0004A30B001C0530";
+
+    fn file(name: &str, contents: &str) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    fn row(records: &Records, index: usize) -> &Row {
+        match &records[index].1 {
+            Record::Row(Ok(row)) => row,
+            other => panic!("row {}: {:?}", index + 1, other),
+        }
+    }
+
+    fn error(records: &Records, index: usize) -> &str {
+        match &records[index].1 {
+            Record::Row(Err(e)) => e,
+            other => panic!("row {}: {:?}", index + 1, other),
+        }
+    }
+
+    #[test]
+    fn reads_ttn_v2_keys_nested_or_at_the_top_level() {
+        let (_dir, path) = file(
+            "ttn.json",
+            &format!(
+                r#"{{"devices": [
+                    {{"app_id": "farm", "dev_id": "nested", "lorawan_device":
+                        {{"app_eui": "{eui}", "app_key": "{key}", "dev_eui": "{dev}"}}}},
+                    {{"app_id": "farm", "dev_id": "flat",
+                        "app_eui": "{eui}", "app_key": "{key}", "dev_eui": "{dev}"}},
+                    {{"app_id": "farm", "dev_id": "abp", "lorawan_device": {{"dev_eui": "{dev}"}}}}
+                ]}}"#,
+                eui = APP_EUI,
+                key = APP_KEY,
+                dev = DEV_EUI
+            ),
+        );
+        let records = ttn_v2(&path, &Defaults::default()).unwrap();
+        assert_eq!(records.len(), 3);
+        for (index, name) in ["nested", "flat"].iter().enumerate() {
+            let row = row(&records, index);
+            assert_eq!(&row.name, name);
+            assert_eq!(row.app_key, APP_KEY);
+            assert_eq!(row.dev_eui, DEV_EUI);
+        }
+        match &records[2] {
+            (3, Record::Skipped { id, reason, .. }) => {
+                assert_eq!(id, "farm/abp");
+                assert_eq!(reason, "no OTAA AppKey");
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_ttn_v3_objects_printed_one_after_another() {
+        let (_dir, path) = file(
+            "tts.json",
+            &format!(
+                r#"{{"ids": {{"device_id": "one", "application_ids": {{"application_id": "farm"}},
+                    "dev_eui": "{dev}", "join_eui": "{eui}"}},
+                    "root_keys": {{"app_key": {{"key": "{key}"}}}}}}
+                {{"ids": {{"device_id": "two", "application_ids": {{"application_id": "farm"}},
+                    "join_eui": "{eui}"}},
+                    "root_keys": {{"app_key": {{"key": "{key}"}}}}}}"#,
+                eui = APP_EUI,
+                key = APP_KEY,
+                dev = DEV_EUI
+            ),
+        );
+        let defaults = Defaults {
+            label_with_app: true,
+            ..Defaults::default()
+        };
+        let records = ttn_v3(&path, &defaults).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(row(&records, 0).name, "one");
+        assert_eq!(row(&records, 0).labels, vec!["farm".to_string()]);
+        assert_eq!(error(&records, 1), "farm/two has no DevEUI");
+    }
+
+    #[test]
+    fn reads_chirpstack_dumps_whatever_the_header_spelling() {
+        let (_dir, path) = file(
+            "chirpstack.csv",
+            &format!(
+                "Dev EUI,Device-Name,APPLICATION_NAME,NwkKey\n\
+                 {dev},sensor,orchard,{key}\n\
+                 {dev},too,many,columns,here\n\
+                 0000000000000001,,orchard,\n",
+                dev = DEV_EUI,
+                key = APP_KEY
+            ),
+        );
+        let records = chirpstack(&path, &Defaults::default()).unwrap();
+        assert_eq!(
+            error(&records, 0),
+            format!("{} has no AppEUI; pass --app-eui", DEV_EUI)
+        );
+
+        let defaults = Defaults {
+            app_eui: Some(APP_EUI.parse().unwrap()),
+            label_with_app: true,
+        };
+        let records = chirpstack(&path, &defaults).unwrap();
+        assert_eq!(records.len(), 3);
+        let first = row(&records, 0);
+        assert_eq!(first.name, "sensor");
+        assert_eq!(first.app_eui, APP_EUI);
+        assert_eq!(first.app_key, APP_KEY);
+        assert_eq!(first.labels, vec!["orchard".to_string()]);
+        assert_eq!(records[1].0, 2);
+        assert!(!error(&records, 1).is_empty());
+        assert!(matches!(records[2].1, Record::Skipped { .. }));
+    }
+
+    #[test]
+    fn rejects_eui_lists_without_an_app_key() {
+        let (_dir, path) = file(
+            "onboarding.csv",
+            &format!(
+                "DevEUI,JoinEUI,AppKey\n\
+                 {dev},{eui},{key}\n\
+                 0000000000000001,{eui},\n",
+                dev = DEV_EUI,
+                eui = APP_EUI,
+                key = APP_KEY
+            ),
+        );
+        let records = eui_csv(&path, &Defaults::default()).unwrap();
+        let first = row(&records, 0);
+        assert_eq!(first.name, DEV_EUI);
+        assert_eq!(first.app_eui, APP_EUI);
+        assert_eq!(error(&records, 1), "0000000000000001 has no AppKey");
+    }
+}
@@ -0,0 +1,1341 @@
+//! Runs the real `helium-console-cli` binary against `mock-console`
+
+use mock_console::{ChirpstackDevice, Fault, MockConsole, TtnDevice, TtsDevice};
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    time::Instant,
+};
+use tempfile::TempDir;
+
+const API_KEY: &str = "q0Tj2Wa4Qe8vUmRjpMK2TP+ImnXbTQy1jD3/5DRqNnA=";
+const APP_EUI: &str = "70B3D57ED0001234";
+const APP_KEY: &str = "0123456789ABCDEF0123456789ABCDEF";
+const DEV_EUI: &str = "0004A30B001C0530";
+
+struct TestEnv {
+    console: MockConsole,
+    dir: TempDir,
+}
+
+impl TestEnv {
+    fn new() -> TestEnv {
+        Self::with_config("request_timeout = 5", "base_delay_ms = 10")
+    }
+
+    /// Points a config file at a fresh mock, with extra top-level `settings`
+    /// and `retry` policy lines
+    fn with_config(settings: &str, retry: &str) -> TestEnv {
+        let console = MockConsole::start(API_KEY).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let config = format!(
+            "key = \"{}\"\nbase_url = \"{}\"\n{}\n[retry]\n{}\n",
+            API_KEY,
+            console.url(),
+            settings,
+            retry
+        );
+        fs::write(dir.path().join(".helium-console-config.toml"), config).unwrap();
+        TestEnv { console, dir }
+    }
+
+    /// UUID of the first device stored by the mock
+    fn first_device_id(&self) -> String {
+        let device = self.console.state().console().devices()[0].clone();
+        serde_json::to_value(&device).unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    /// Config directory of the CLI under test, in place of ~/.config
+    fn xdg_config_home(&self) -> PathBuf {
+        self.dir.path().join("xdg")
+    }
+
+    fn cli(&self, args: &[&str]) -> Output {
+        self.cli_with_env(args, &[])
+    }
+
+    fn cli_with_env(&self, args: &[&str], vars: &[(&str, &str)]) -> Output {
+        self.command(args)
+            .envs(vars.iter().cloned())
+            .output()
+            .unwrap()
+    }
+
+    /// Runs the CLI with `input` as the answers to its prompts
+    fn cli_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_helium-console-cli"));
+        command
+            .args(args)
+            .current_dir(self.dir.path())
+            .env("XDG_CONFIG_HOME", self.xdg_config_home())
+            .env_remove("HELIUM_CONSOLE_PROFILE")
+            .env_remove("HELIUM_CONSOLE_API_KEY")
+            .env_remove("HELIUM_CONSOLE_BASE_URL")
+            .env_remove("HELIUM_CONSOLE_TIMEOUT");
+        command
+    }
+
+    fn create_device(&self, dev_eui: &str, name: &str) -> Output {
+        self.cli(&["device", "create", APP_EUI, APP_KEY, dev_eui, name])
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn creates_lists_and_deletes_devices() {
+    let env = TestEnv::new();
+
+    let created = env.create_device(DEV_EUI, "sensor");
+    assert!(created.status.success(), "{}", stdout(&created));
+    assert!(stdout(&created).contains(DEV_EUI));
+
+    let listed = env.cli(&["device", "list", "--oneline"]);
+    assert!(listed.status.success());
+    assert!(stdout(&listed).contains(DEV_EUI));
+    assert!(!stdout(&listed).contains(APP_KEY));
+
+    let deleted = env.cli(&["device", "delete", APP_EUI, APP_KEY, DEV_EUI]);
+    assert!(deleted.status.success(), "{}", stdout(&deleted));
+    assert!(stdout(&deleted).contains("Device delete successful"));
+    assert!(env.console.state().console().devices().is_empty());
+}
+
+#[test]
+fn duplicate_device_is_a_conflict() {
+    let env = TestEnv::new();
+
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    let duplicate = env.create_device(DEV_EUI, "sensor");
+    assert!(!duplicate.status.success());
+    assert!(stdout(&duplicate).contains("Conflict"));
+}
+
+#[test]
+fn rejected_key_is_unauthorized() {
+    let env = TestEnv::new();
+    env.console.inject(Fault::status(401));
+
+    let listed = env.cli(&["device", "list"]);
+    assert!(!listed.status.success());
+    assert!(stdout(&listed).contains("Unauthorized"));
+}
+
+#[test]
+fn server_errors_are_retried() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    env.console
+        .inject(Fault::status(500).on("GET", "api/v1/devices").times(2));
+
+    let listed = env.cli(&["devices", "all"]);
+    assert!(listed.status.success(), "{}", stdout(&listed));
+    assert!(stdout(&listed).contains(DEV_EUI));
+}
+
+#[test]
+fn rate_limits_honour_retry_after() {
+    let env = TestEnv::new();
+    env.console
+        .inject(Fault::status(429).retry_after(1).on("GET", "api/v1/labels"));
+
+    let started = Instant::now();
+    let listed = env.cli(&["label", "list"]);
+    assert!(listed.status.success(), "{}", stdout(&listed));
+    assert!(started.elapsed().as_secs_f64() >= 1.0);
+}
+
+#[test]
+fn device_creation_is_not_retried() {
+    let env = TestEnv::new();
+    env.console
+        .inject(Fault::status(500).on("POST", "api/v1/devices"));
+
+    let created = env.create_device(DEV_EUI, "sensor");
+    assert!(!created.status.success());
+    assert!(stdout(&created).contains("Server error"));
+}
+
+#[test]
+fn slow_responses_time_out() {
+    let env = TestEnv::with_config("request_timeout = 1", "max_attempts = 1");
+    env.console.inject(Fault::delay(3000));
+
+    let listed = env.cli(&["label", "list"]);
+    assert!(!listed.status.success());
+    assert!(stdout(&listed).contains("Failed to connect"));
+}
+
+#[test]
+fn follows_pagination() {
+    let env = TestEnv::with_config("request_timeout = 5\npage_size = 2", "base_delay_ms = 10");
+    for (index, dev_eui) in ["0000000000000001", "0000000000000002", "0000000000000003"]
+        .iter()
+        .enumerate()
+    {
+        let name = format!("sensor-{}", index);
+        assert!(env.create_device(dev_eui, &name).status.success());
+    }
+
+    let listed = env.cli(&["devices", "all"]);
+    assert!(listed.status.success(), "{}", stdout(&listed));
+    assert_eq!(stdout(&listed).lines().count(), 3);
+}
+
+#[test]
+fn labels_devices() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    assert!(env.cli(&["label", "create", "warehouse"]).status.success());
+
+    let device_id = env.first_device_id();
+    let label_id = env.console.state().console().labels()[0].id().clone();
+
+    let added = env.cli(&["device", "add-label", &device_id, &label_id]);
+    assert!(added.status.success(), "{}", stdout(&added));
+    let listed = env.cli(&["devices", "all"]);
+    assert!(stdout(&listed).contains(&label_id));
+
+    let removed = env.cli(&["device", "remove-label", &device_id, &label_id]);
+    assert!(removed.status.success(), "{}", stdout(&removed));
+    let removed_again = env.cli(&["device", "remove-label", &device_id, &label_id]);
+    assert!(stdout(&removed_again).contains("Device label not found"));
+
+    let deleted = env.cli(&["label", "delete-by-id", &label_id]);
+    assert!(stdout(&deleted).contains("Label delete successful"));
+    assert!(env.console.state().console().labels().is_empty());
+}
+
+#[test]
+fn pauses_devices() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    let device_id = env.first_device_id();
+
+    let updated = env.cli(&["device", "update", &device_id, "--active", "false"]);
+    assert!(updated.status.success(), "{}", stdout(&updated));
+    let listed = env.cli(&["devices", "all"]);
+    assert!(stdout(&listed).contains("\"active\":false"));
+}
+
+#[test]
+fn imports_devices_from_csv() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "existing").status.success());
+    let csv = format!(
+        "app_eui,app_key,dev_eui,name,labels\n\
+         {app_eui},{app_key},{dev_eui},existing,\n\
+         {app_eui},{app_key},0000000000000001,one,warehouse;north\n\
+         {app_eui},{app_key},0000000000000002,two,warehouse\n",
+        app_eui = APP_EUI,
+        app_key = APP_KEY,
+        dev_eui = DEV_EUI
+    );
+    fs::write(env.dir.path().join("devices.csv"), csv).unwrap();
+
+    let unknown_labels = env.cli(&["device", "import", "--file", "devices.csv"]);
+    assert!(!unknown_labels.status.success());
+    assert!(stdout(&unknown_labels).contains("No label named"));
+    assert_eq!(env.console.state().console().devices().len(), 1);
+
+    let imported = env.cli(&[
+        "device",
+        "import",
+        "--file",
+        "devices.csv",
+        "--create-missing",
+    ]);
+    assert!(imported.status.success(), "{}", stdout(&imported));
+    assert!(stdout(&imported).contains("2 created, 1 already existed, 0 failed"));
+    assert_eq!(env.console.state().console().devices().len(), 3);
+    assert_eq!(env.console.state().console().labels().len(), 2);
+
+    let report = fs::read_to_string(env.dir.path().join("devices.report.csv")).unwrap();
+    assert!(report.contains("already_exists"));
+    assert_eq!(report.matches(",created,").count(), 2);
+}
+
+#[test]
+fn invalid_import_rows_send_nothing() {
+    let env = TestEnv::new();
+    let ndjson = format!(
+        "{{\"app_eui\":\"{}\",\"app_key\":\"{}\",\"dev_eui\":\"{}\",\"name\":\"ok\"}}\n\
+         {{\"app_eui\":\"{}\",\"app_key\":\"short\",\"dev_eui\":\"0000000000000001\",\"name\":\"bad\"}}\n",
+        APP_EUI, APP_KEY, DEV_EUI, APP_EUI
+    );
+    fs::write(env.dir.path().join("devices.ndjson"), ndjson).unwrap();
+
+    let imported = env.cli(&["device", "import", "--file", "devices.ndjson"]);
+    assert!(!imported.status.success());
+    assert!(stdout(&imported).contains("row 2"));
+    assert!(env.console.state().console().devices().is_empty());
+}
+
+#[test]
+fn exports_devices_with_label_names() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    assert!(env.cli(&["label", "create", "warehouse"]).status.success());
+    let device_id = env.first_device_id();
+    let label_id = env.console.state().console().labels()[0].id().clone();
+    assert!(env
+        .cli(&["device", "add-label", &device_id, &label_id])
+        .status
+        .success());
+
+    let exported = env.cli(&["device", "export", "--columns", "name,dev_eui,labels"]);
+    assert!(exported.status.success(), "{}", stdout(&exported));
+    assert_eq!(
+        stdout(&exported),
+        format!("name,dev_eui,labels\nsensor,{},warehouse\n", DEV_EUI)
+    );
+
+    let yaml = env.cli(&["device", "export", "--format", "yaml"]);
+    assert!(yaml.status.success(), "{}", stdout(&yaml));
+    assert!(stdout(&yaml).contains("- warehouse"));
+    assert!(!stdout(&yaml).contains("app_key"));
+
+    let keys = env.cli(&["device", "export", "--columns", "dev_eui,app_key"]);
+    assert!(!keys.status.success());
+    let revealed = env.cli(&[
+        "device",
+        "export",
+        "--columns",
+        "dev_eui,app_key",
+        "--reveal-keys",
+    ]);
+    assert!(stdout(&revealed).contains(APP_KEY));
+}
+
+#[test]
+fn applies_manifests_idempotently() {
+    let env = TestEnv::new();
+    assert!(env
+        .create_device("0000000000000009", "stray")
+        .status
+        .success());
+    let manifest = |name: &str, label: &str| {
+        format!(
+            "labels: [spare]\n\
+             devices:\n  \
+               - name: {}\n    \
+                 dev_eui: {}\n    \
+                 app_eui: {}\n    \
+                 app_key: {}\n    \
+                 labels: [{}]\n",
+            name, DEV_EUI, APP_EUI, APP_KEY, label
+        )
+    };
+    let fleet = env.dir.path().join("fleet.yaml");
+
+    fs::write(&fleet, manifest("sensor", "warehouse")).unwrap();
+    let applied = env.cli(&["apply", "-f", "fleet.yaml", "--yes"]);
+    assert!(applied.status.success(), "{}", stdout(&applied));
+    assert!(stdout(&applied).contains("Plan: 3 to create, 0 to change, 0 to delete."));
+    assert_eq!(env.console.state().console().devices().len(), 2);
+
+    let rerun = env.cli(&["apply", "-f", "fleet.yaml", "--yes"]);
+    assert!(stdout(&rerun).contains("No changes"), "{}", stdout(&rerun));
+
+    fs::write(&fleet, manifest("renamed", "north")).unwrap();
+    let changed = env.cli(&["apply", "-f", "fleet.yaml", "--yes", "--prune"]);
+    assert!(changed.status.success(), "{}", stdout(&changed));
+    let output = stdout(&changed);
+    assert!(
+        output.contains("name: \"sensor\" -> \"renamed\""),
+        "{}",
+        output
+    );
+    assert!(output.contains("- label warehouse"));
+    assert!(output.contains("Plan: 1 to create, 1 to change, 2 to delete."));
+
+    let devices = env.console.state().console().devices();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].name(), "renamed");
+    let labels = env.console.state().console().labels();
+    assert_eq!(labels.len(), 2);
+    assert!(env
+        .cli(&["apply", "-f", "fleet.yaml", "--prune"])
+        .status
+        .success());
+
+    // label names follow the label_match policy, case-insensitive by default
+    fs::write(&fleet, manifest("renamed", "NORTH")).unwrap();
+    let case = env.cli(&["apply", "-f", "fleet.yaml", "--prune", "--yes"]);
+    assert!(stdout(&case).contains("No changes"), "{}", stdout(&case));
+
+    // the Console cannot rotate an AppKey, so a different one is refused
+    let rotated = manifest("renamed", "north").replace(APP_KEY, &"F".repeat(32));
+    fs::write(&fleet, rotated).unwrap();
+    let refused = env.cli(&["apply", "-f", "fleet.yaml", "--yes"]);
+    assert!(!refused.status.success());
+    assert!(
+        stdout(&refused).contains("different app_key for \"renamed\""),
+        "{}",
+        stdout(&refused)
+    );
+}
+
+#[test]
+fn dry_run_sends_no_changes() {
+    let env = TestEnv::new();
+
+    let created = env.cli(&[
+        "--dry-run",
+        "device",
+        "create",
+        APP_EUI,
+        APP_KEY,
+        DEV_EUI,
+        "sensor",
+    ]);
+    assert!(created.status.success(), "{}", stdout(&created));
+    let output = stdout(&created);
+    assert!(output.contains(&format!("POST {}/api/v1/devices", env.console.url())));
+    assert!(output.contains("\"app_key\":\"********\""), "{}", output);
+    assert!(!output.contains(APP_KEY));
+    assert!(env.console.state().console().devices().is_empty());
+
+    // lookups still run, so deleting an unknown device fails as usual
+    let missing = env.cli(&["--dry-run", "device", "delete", APP_EUI, APP_KEY, DEV_EUI]);
+    assert!(!missing.status.success());
+
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    let deleted = env.cli(&["device", "delete", APP_EUI, APP_KEY, DEV_EUI, "--dry-run"]);
+    assert!(deleted.status.success(), "{}", stdout(&deleted));
+    let url = format!(
+        "DELETE {}/api/v1/devices/{}",
+        env.console.url(),
+        env.first_device_id()
+    );
+    assert!(stdout(&deleted).contains(&url), "{}", stdout(&deleted));
+    assert_eq!(env.console.state().console().devices().len(), 1);
+}
+
+#[test]
+fn selects_config_profiles() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+
+    // the XDG file takes over from the legacy one in the working directory
+    let config = format!(
+        "default_profile = \"broken\"\n\
+         [profiles.broken]\n\
+         key = \"{key}\"\n\
+         base_url = \"http://127.0.0.1:1\"\n\
+         request_timeout = 5\n\
+         [profiles.broken.retry]\n\
+         max_attempts = 1\n\
+         [profiles.staging]\n\
+         key = \"{key}\"\n\
+         base_url = \"{url}\"\n\
+         request_timeout = 5\n",
+        key = API_KEY,
+        url = env.console.url()
+    );
+    let dir = env.xdg_config_home().join("helium-console");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("config.toml"), config).unwrap();
+
+    assert!(!env.cli(&["device", "list"]).status.success());
+
+    let flagged = env.cli(&["device", "list", "--profile", "staging"]);
+    assert!(flagged.status.success(), "{}", stdout(&flagged));
+    assert!(stdout(&flagged).contains(DEV_EUI));
+
+    let from_env = env.cli_with_env(
+        &["device", "list"],
+        &[("HELIUM_CONSOLE_PROFILE", "staging")],
+    );
+    assert!(from_env.status.success(), "{}", stdout(&from_env));
+
+    let missing = env.cli(&["device", "list", "--profile", "prod"]);
+    assert!(stdout(&missing).contains("Available profiles: broken, staging"));
+}
+
+#[test]
+fn manages_config_profiles() {
+    let env = TestEnv::new();
+    let url = env.console.url();
+
+    let invalid = env.cli(&["config", "init", "--api-key", "not-a-key"]);
+    assert!(!invalid.status.success());
+    assert!(!env.xdg_config_home().exists());
+
+    let created = env.cli(&[
+        "config",
+        "init",
+        "--profile",
+        "staging",
+        "--api-key",
+        API_KEY,
+        "--base-url",
+        &url,
+    ]);
+    assert!(created.status.success(), "{}", stdout(&created));
+
+    let shown = env.cli(&["config", "show", "--profile", "staging"]);
+    assert!(stdout(&shown).contains(&url));
+    assert!(!stdout(&shown).contains(API_KEY));
+
+    let set = env.cli(&[
+        "config",
+        "set",
+        "retry.max_attempts",
+        "5",
+        "--profile",
+        "staging",
+    ]);
+    assert!(set.status.success(), "{}", stdout(&set));
+    let shown = env.cli(&["config", "show", "--profile", "staging"]);
+    assert!(stdout(&shown).contains("max_attempts = 5"));
+    let rejected = env.cli(&[
+        "config",
+        "set",
+        "base_url",
+        "nowhere",
+        "--profile",
+        "staging",
+    ]);
+    assert!(!rejected.status.success());
+
+    // the legacy file was carried over as the "default" profile
+    let validated = env.cli(&["config", "validate"]);
+    assert!(validated.status.success(), "{}", stdout(&validated));
+    assert!(stdout(&validated).contains("default: ok"));
+
+    let whoami = env.cli(&["config", "whoami", "--profile", "staging"]);
+    assert!(whoami.status.success(), "{}", stdout(&whoami));
+    assert!(stdout(&whoami).contains("In-memory organization"));
+}
+
+#[test]
+fn overrides_take_precedence() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    let url = env.console.url();
+    fs::remove_file(env.dir.path().join(".helium-console-config.toml")).unwrap();
+
+    // CI style: no config file, everything from the environment
+    let vars = [
+        ("HELIUM_CONSOLE_API_KEY", API_KEY),
+        ("HELIUM_CONSOLE_BASE_URL", url.as_str()),
+        ("HELIUM_CONSOLE_TIMEOUT", "5"),
+    ];
+    let listed = env.cli_with_env(&["device", "list"], &vars);
+    assert!(listed.status.success(), "{}", stdout(&listed));
+    assert!(stdout(&listed).contains(DEV_EUI));
+
+    // a flag beats the env var
+    let broken = env.cli_with_env(
+        &["device", "list", "--base-url", "http://127.0.0.1:1"],
+        &vars,
+    );
+    assert!(!broken.status.success());
+
+    let shown = env.cli_with_env(&["config", "show", "--timeout", "7"], &vars);
+    let output = stdout(&shown);
+    assert!(shown.status.success(), "{}", output);
+    assert!(
+        output.contains("key: env HELIUM_CONSOLE_API_KEY"),
+        "{}",
+        output
+    );
+    assert!(output.contains("base_url: env HELIUM_CONSOLE_BASE_URL"));
+    assert!(output.contains("request_timeout: flag --timeout"));
+    assert!(output.contains("request_timeout = 7"));
+    assert!(!output.contains(API_KEY));
+}
+
+#[test]
+fn labels_by_name() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    let device_id = env.first_device_id();
+
+    let unknown = env.cli(&["device", "add-label", &device_id, "Warehouse-A"]);
+    assert!(!unknown.status.success());
+    assert!(stdout(&unknown).contains("--create-missing"));
+    assert!(env.console.state().console().labels().is_empty());
+
+    let added = env.cli(&[
+        "device",
+        "add-label",
+        &device_id,
+        "Warehouse-A",
+        "--create-missing",
+    ]);
+    assert!(added.status.success(), "{}", stdout(&added));
+    assert_eq!(
+        env.console.state().console().labels()[0].name(),
+        "Warehouse-A"
+    );
+
+    let devices = env.cli(&["label", "devices", "Warehouse-A"]);
+    assert!(stdout(&devices).contains(DEV_EUI), "{}", stdout(&devices));
+
+    let renamed = env.cli(&["label", "rename", "Warehouse-A", "Warehouse-B"]);
+    assert!(renamed.status.success(), "{}", stdout(&renamed));
+    let got = env.cli(&["label", "get", "Warehouse-B"]);
+    assert!(got.status.success(), "{}", stdout(&got));
+
+    let removed = env.cli(&["device", "remove-label", &device_id, "Warehouse-B"]);
+    assert!(removed.status.success(), "{}", stdout(&removed));
+    let deleted = env.cli(&["label", "delete", "Warehouse-B"]);
+    assert!(stdout(&deleted).contains("Label delete successful"));
+    assert!(env.console.state().console().labels().is_empty());
+}
+
+#[test]
+fn label_case_variants() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    let device_id = env.first_device_id();
+    assert!(env
+        .cli(&["label", "create", "Warehouse-A"])
+        .status
+        .success());
+
+    let added = env.cli(&["device", "add-label", &device_id, "warehouse-a"]);
+    assert!(added.status.success(), "{}", stdout(&added));
+    assert_eq!(env.console.state().console().labels().len(), 1);
+
+    assert!(env
+        .cli(&["label", "create", "WAREHOUSE-A"])
+        .status
+        .success());
+    let got = env.cli(&["label", "get", "WAREHOUSE-A"]);
+    assert!(stdout(&got).contains("\"WAREHOUSE-A\""), "{}", stdout(&got));
+    let warning = String::from_utf8_lossy(&got.stderr);
+    assert!(warning.contains("matches several labels"), "{}", warning);
+
+    // neither variant is named "warehouse-a": reads pick the first by name,
+    // changes are refused
+    let first = env.cli(&["label", "get", "warehouse-a"]);
+    assert!(first.status.success(), "{}", stdout(&first));
+    assert!(
+        stdout(&first).contains("\"WAREHOUSE-A\""),
+        "{}",
+        stdout(&first)
+    );
+    for args in &[
+        &["label", "delete", "warehouse-a"][..],
+        &["label", "rename", "warehouse-a", "north"][..],
+        &["device", "add-label", &device_id, "warehouse-a"][..],
+        &["device", "remove-label", &device_id, "warehouse-a"][..],
+    ] {
+        let refused = env.cli(args);
+        assert!(!refused.status.success(), "{:?}", args);
+        assert!(
+            stdout(&refused).contains("\"warehouse-a\" matches 2 labels"),
+            "{}",
+            stdout(&refused)
+        );
+    }
+    let labels = env.console.state().console().labels();
+    assert_eq!(labels.len(), 2);
+    assert!(stdout(&env.cli(&["label", "delete", "warehouse-a"])).contains(labels[1].id()));
+
+    let exact = env.cli(&["label", "get", "warehouse-a"]);
+    assert!(exact.status.success(), "{}", stdout(&exact));
+    assert!(env
+        .cli(&["config", "set", "label_match", "exact"])
+        .status
+        .success());
+    let missing = env.cli(&["label", "get", "warehouse-a"]);
+    assert!(!missing.status.success());
+}
+
+#[test]
+fn device_selectors() {
+    let env = TestEnv::new();
+    const OTHER_EUI: &str = "0004A30B001C0531";
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    assert!(env.create_device(OTHER_EUI, "sensor").status.success());
+
+    let by_eui = env.cli(&["device", "get", "--dev-eui", DEV_EUI]);
+    assert!(by_eui.status.success(), "{}", stdout(&by_eui));
+    assert!(stdout(&by_eui).contains(DEV_EUI));
+    assert!(!stdout(&by_eui).contains(OTHER_EUI));
+
+    let ambiguous = env.cli(&["device", "get", "--name", "sensor"]);
+    assert!(!ambiguous.status.success());
+    assert!(
+        stdout(&ambiguous).contains("2 devices have name \"sensor\""),
+        "{}",
+        stdout(&ambiguous)
+    );
+
+    let unknown = env.cli(&["device", "get", "--dev-eui", "0004A30B001C0532"]);
+    assert!(!unknown.status.success());
+    assert!(stdout(&unknown).contains("No device with dev_eui"));
+
+    let both = env.cli(&["device", "get", "--name", "sensor", "--dev-eui", DEV_EUI]);
+    assert!(!both.status.success());
+
+    let device_id = env.first_device_id();
+    assert!(env
+        .cli(&[
+            "device",
+            "add-label",
+            &device_id,
+            "Warehouse-A",
+            "--create-missing"
+        ])
+        .status
+        .success());
+    let deleted = env.cli(&["device", "delete", "--label", "warehouse-a"]);
+    assert!(deleted.status.success(), "{}", stdout(&deleted));
+    assert!(stdout(&deleted).contains("Device delete successful"));
+    assert_eq!(env.console.state().console().devices().len(), 1);
+
+    let by_credentials = env.cli(&["device", "get", APP_EUI, APP_KEY, OTHER_EUI]);
+    assert!(
+        by_credentials.status.success(),
+        "{}",
+        stdout(&by_credentials)
+    );
+}
+
+#[test]
+fn filters_and_sorts_devices() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor-b").status.success());
+    assert!(env
+        .create_device("0004A30B001C0531", "sensor-a")
+        .status
+        .success());
+    assert!(env
+        .create_device("0004A30B001C0532", "gateway")
+        .status
+        .success());
+    let paused = env.first_device_id();
+    assert!(env
+        .cli(&["device", "update", &paused, "--active", "false"])
+        .status
+        .success());
+
+    let inactive = env.cli(&["devices", "all", "--active", "false"]);
+    assert!(inactive.status.success(), "{}", stdout(&inactive));
+    assert_eq!(stdout(&inactive).lines().count(), 1);
+    assert!(stdout(&inactive).contains(DEV_EUI));
+
+    let sorted = env.cli(&[
+        "devices",
+        "all",
+        "--name-glob",
+        "sensor-*",
+        "--sort-by",
+        "name",
+    ]);
+    let names: Vec<String> = stdout(&sorted)
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["name"].to_string())
+        .collect();
+    assert_eq!(names, vec!["\"sensor-a\"", "\"sensor-b\""]);
+
+    let limited = env.cli(&["device", "list", "--oneline", "--limit", "2"]);
+    let listed: serde_json::Value = serde_json::from_str(&stdout(&limited)).unwrap();
+    assert_eq!(listed["devices"].as_array().unwrap().len(), 2);
+
+    // the mock has never seen an uplink, so no device connected recently
+    let stale = env.cli(&["devices", "all", "--last-connected-before", "7d"]);
+    assert_eq!(stdout(&stale).lines().count(), 3);
+    let invalid = env.cli(&["devices", "all", "--last-connected-before", "week"]);
+    assert!(!invalid.status.success());
+}
+
+#[test]
+fn output_formats() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    assert!(env.cli(&["label", "create", "warehouse"]).status.success());
+
+    let table = env.cli(&["device", "list", "--output", "table"]);
+    assert!(table.status.success(), "{}", stdout(&table));
+    let lines: Vec<String> = stdout(&table).lines().map(str::to_string).collect();
+    assert!(lines[0].contains("DEV EUI"), "{}", stdout(&table));
+    assert!(lines[1].contains(DEV_EUI));
+    assert!(!lines[1].contains(APP_KEY));
+
+    let json = env.cli(&["device", "list", "--output", "json"]);
+    let parsed: serde_json::Value = serde_json::from_str(&stdout(&json)).unwrap();
+    assert_eq!(parsed["devices"][0]["dev_eui"], DEV_EUI);
+
+    let labels = env.cli(&["label", "list", "--output", "yaml"]);
+    assert!(
+        stdout(&labels).contains("name: warehouse"),
+        "{}",
+        stdout(&labels)
+    );
+
+    let all = env.cli(&["devices", "all", "--output", "table"]);
+    assert!(stdout(&all).contains("XOR FILTER"), "{}", stdout(&all));
+
+    let got = env.cli(&["device", "get", "--dev-eui", DEV_EUI, "--output", "json"]);
+    let parsed: serde_json::Value = serde_json::from_str(&stdout(&got)).unwrap();
+    assert_eq!(parsed["name"], "sensor");
+
+    let invalid = env.cli(&["device", "list", "--output", "xml"]);
+    assert!(!invalid.status.success());
+}
+
+#[test]
+fn ttn_import_with_yes_needs_every_answer() {
+    let env = TestEnv::new();
+
+    let no_code = env.cli(&["ttn", "import", "--yes", "--all-apps"]);
+    assert!(!no_code.status.success());
+    assert!(
+        stdout(&no_code).contains("--access-code"),
+        "{}",
+        stdout(&no_code)
+    );
+
+    let no_apps = env.cli(&["ttn", "import", "--yes", "--access-code", "abc"]);
+    assert!(!no_apps.status.success());
+    assert!(stdout(&no_apps).contains("--apps or --all-apps"));
+
+    let prompt = env.cli(&[
+        "ttn",
+        "import",
+        "--yes",
+        "--access-code",
+        "abc",
+        "--apps",
+        "app1,app2",
+        "--delete-from-ttn",
+        "prompt",
+    ]);
+    assert!(!prompt.status.success());
+    assert!(stdout(&prompt).contains("cannot be combined with --yes"));
+
+    let both = env.cli(&["ttn", "import", "--apps", "app1", "--all-apps"]);
+    assert!(!both.status.success());
+}
+
+#[test]
+fn imports_from_the_things_stack() {
+    let env = TestEnv::new();
+    let tts_device = |device_id: &str, dev_eui: Option<&str>, app_key: Option<&str>| TtsDevice {
+        app_id: "greenhouse".to_string(),
+        device_id: device_id.to_string(),
+        name: String::new(),
+        dev_eui: dev_eui.map(str::to_string),
+        join_eui: Some(APP_EUI.to_string()),
+        app_key: app_key.map(str::to_string),
+    };
+    let state = env.console.state();
+    state.add_tts_device(tts_device("otaa-1", Some(DEV_EUI), Some(APP_KEY)));
+    state.add_tts_device(tts_device("abp-1", None, None));
+    state.add_tts_device(tts_device("no-key", Some("0004A30B001C0531"), None));
+
+    let url = env.console.url();
+    let imported = env.cli_with_env(
+        &[
+            "ttn",
+            "import-v3",
+            "--cluster-url",
+            &url,
+            "--all-apps",
+            "--label-with-app-id",
+        ],
+        &[("TTS_API_KEY", mock_console::TTS_API_KEY)],
+    );
+    assert!(imported.status.success(), "{}", stdout(&imported));
+    assert!(stdout(&imported).contains("1 created, 0 already existed, 0 failed"));
+    assert!(stdout(&imported).contains("Skipping greenhouse/abp-1"));
+    assert!(stdout(&imported).contains("Skipping greenhouse/no-key"));
+
+    let devices = state.console().devices();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].name(), "otaa-1");
+    assert_eq!(state.console().labels()[0].name(), "greenhouse");
+
+    let unauthorized = env.cli(&[
+        "ttn",
+        "import-v3",
+        "--cluster-url",
+        &url,
+        "--tts-api-key",
+        API_KEY,
+        "--apps",
+        "greenhouse",
+    ]);
+    assert!(!unauthorized.status.success());
+}
+
+#[test]
+fn imports_from_chirpstack() {
+    let env = TestEnv::new();
+    let chirpstack_device = |dev_eui: &str, nwk_key: Option<&str>| ChirpstackDevice {
+        application_id: "7".to_string(),
+        application_name: "orchard".to_string(),
+        dev_eui: dev_eui.to_string(),
+        name: format!("sensor-{}", &dev_eui[12..]),
+        device_profile_name: "RAK7204".to_string(),
+        nwk_key: nwk_key.map(str::to_string),
+    };
+    let state = env.console.state();
+    state.add_chirpstack_device(chirpstack_device(DEV_EUI, Some(APP_KEY)));
+    state.add_chirpstack_device(chirpstack_device("0004A30B001C0531", None));
+
+    let url = env.console.url();
+    let imported = env.cli_with_env(
+        &[
+            "chirpstack",
+            "import",
+            "--server-url",
+            &url,
+            "--app-eui",
+            APP_EUI,
+            "--applications",
+            "7",
+            "--label-with",
+            "device-profile",
+        ],
+        &[("CHIRPSTACK_API_TOKEN", mock_console::CHIRPSTACK_API_TOKEN)],
+    );
+    assert!(imported.status.success(), "{}", stdout(&imported));
+    assert!(stdout(&imported).contains("1 created, 0 already existed, 0 failed"));
+    assert!(stdout(&imported).contains("Skipping orchard/"));
+
+    let devices = state.console().devices();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].app_eui().to_string(), APP_EUI);
+    assert_eq!(state.console().labels()[0].name(), "RAK7204");
+
+    let unknown = env.cli_with_env(
+        &[
+            "chirpstack",
+            "import",
+            "--server-url",
+            &url,
+            "--app-eui",
+            APP_EUI,
+            "--applications",
+            "8",
+        ],
+        &[("CHIRPSTACK_API_TOKEN", mock_console::CHIRPSTACK_API_TOKEN)],
+    );
+    assert!(!unknown.status.success());
+    assert!(stdout(&unknown).contains("No ChirpStack application with ID 8"));
+}
+
+#[test]
+fn masks_keys_only_where_printed() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+
+    for args in &[
+        &["device", "list", "--oneline"][..],
+        &["device", "list", "--output", "json"][..],
+        &["device", "list", "--output", "yaml"][..],
+        &["device", "get", "--dev-eui", DEV_EUI][..],
+    ] {
+        let masked = env.cli(args);
+        assert!(masked.status.success(), "{}", stdout(&masked));
+        assert!(stdout(&masked).contains("********"), "{:?}", args);
+        assert!(!stdout(&masked).contains(APP_KEY), "{:?}", args);
+
+        let mut revealing = vec!["--reveal-keys"];
+        revealing.extend_from_slice(args);
+        assert!(stdout(&env.cli(&revealing)).contains(APP_KEY), "{:?}", args);
+    }
+
+    // the mock decodes what the client sends, so keys must go out in full
+    let device = &env.console.state().console().devices()[0];
+    assert_eq!(device.app_key().expose_secret().to_string(), APP_KEY);
+}
+
+#[test]
+fn oneline_only_combines_with_json_output() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+
+    let table = env.cli(&["device", "list", "--oneline", "--output", "table"]);
+    assert!(!table.status.success());
+    assert!(stdout(&table).contains("--oneline cannot be combined with --output"));
+
+    let json = env.cli(&["device", "list", "--oneline", "--output", "json"]);
+    assert!(json.status.success(), "{}", stdout(&json));
+    let listed: serde_json::Value = serde_json::from_str(&stdout(&json)).unwrap();
+    assert_eq!(listed["devices"][0]["name"], "sensor");
+}
+
+#[test]
+fn ttn_import_keeps_devices_it_could_not_import() {
+    let env = TestEnv::new();
+    let state = env.console.state();
+    for (dev_id, dev_eui) in &[("failing", DEV_EUI), ("moving", "0004A30B001C0531")] {
+        state.add_ttn_device(TtnDevice {
+            app_id: "warehouse".to_string(),
+            dev_id: dev_id.to_string(),
+            dev_eui: dev_eui.to_string(),
+            app_eui: APP_EUI.to_string(),
+            app_key: Some(APP_KEY.to_string()),
+        });
+    }
+    state.add_ttn_access_code("code-1");
+    env.console
+        .inject(Fault::status(400).on("POST", "/api/v1/devices"));
+
+    let imported = env.cli(&[
+        "ttn",
+        "import",
+        "--yes",
+        "--access-code",
+        "code-1",
+        "--all-apps",
+        "--delete-from-ttn",
+        "always",
+        "--account-url",
+        &env.console.ttn_account_url(),
+        "--handler-url",
+        &env.console.ttn_handler_url(),
+    ]);
+    assert!(imported.status.success(), "{}", stdout(&imported));
+    assert!(stdout(&imported).contains("Keeping the device in TTN, since it was not imported"));
+
+    let devices = state.console().devices();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].name(), "moving");
+    let remaining: Vec<String> = state
+        .ttn_devices()
+        .into_iter()
+        .map(|device| device.dev_id)
+        .collect();
+    assert_eq!(remaining, vec!["failing"]);
+
+    // the access code was used up by the first run
+    let reused = env.cli(&[
+        "ttn",
+        "import",
+        "--yes",
+        "--access-code",
+        "code-1",
+        "--all-apps",
+        "--account-url",
+        &env.console.ttn_account_url(),
+    ]);
+    assert!(!reused.status.success());
+    assert!(stdout(&reused).contains("Authorization code not found"));
+}
+
+/// Registers `apps` TTN applications of one device each, and returns the
+/// arguments pointing ttn import at the stand-in
+fn ttn_apps(env: &TestEnv, apps: usize) -> Vec<String> {
+    for app in 1..=apps {
+        env.console.state().add_ttn_device(TtnDevice {
+            app_id: format!("app-{:02}", app),
+            dev_id: format!("device-{:02}", app),
+            dev_eui: format!("0004A30B001C05{:02X}", app),
+            app_eui: APP_EUI.to_string(),
+            app_key: Some(APP_KEY.to_string()),
+        });
+    }
+    vec![
+        "--account-url".to_string(),
+        env.console.ttn_account_url(),
+        "--handler-url".to_string(),
+        env.console.ttn_handler_url(),
+    ]
+}
+
+#[test]
+fn ttn_import_with_yes_resumes_after_a_consumed_token() {
+    let env = TestEnv::new();
+    let urls = ttn_apps(&env, 11);
+    let state = env.console.state();
+    state.add_ttn_access_code("code-1");
+    state.add_ttn_access_code("code-2");
+
+    let mut args = vec![
+        "ttn",
+        "import",
+        "--yes",
+        "--access-code",
+        "code-1",
+        "--all-apps",
+    ];
+    args.extend(urls.iter().map(String::as_str));
+    let stopped = env.cli(&args);
+    assert!(!stopped.status.success());
+    assert!(
+        stdout(&stopped).contains(
+            "TTN needs a new access code for batch 2 of 2. The batches before it were \
+             imported; run again with --apps app-11"
+        ),
+        "{}",
+        stdout(&stopped)
+    );
+    assert_eq!(state.console().devices().len(), 10);
+
+    let mut args = vec![
+        "ttn",
+        "import",
+        "--yes",
+        "--access-code",
+        "code-2",
+        "--apps",
+        "app-11",
+    ];
+    args.extend(urls.iter().map(String::as_str));
+    let resumed = env.cli(&args);
+    assert!(resumed.status.success(), "{}", stdout(&resumed));
+    assert_eq!(state.console().devices().len(), 11);
+}
+
+#[test]
+fn ttn_import_asks_for_an_access_code_per_batch() {
+    let env = TestEnv::new();
+    let urls = ttn_apps(&env, 11);
+    let state = env.console.state();
+    state.add_ttn_access_code("code-1");
+    state.add_ttn_access_code("code-2");
+
+    let mut args = vec![
+        "ttn",
+        "import",
+        "--access-code",
+        "code-1",
+        "--all-apps",
+        "--delete-from-ttn",
+        "always",
+    ];
+    args.extend(urls.iter().map(String::as_str));
+    // import all, do not label all, skip labelling, then the second code
+    let imported = env.cli_with_input(&args, "y\nn\ny\ncode-2\n");
+    assert!(imported.status.success(), "{}", stdout(&imported));
+    assert!(stdout(&imported).contains("TTN needs a new access code for batch 2 of 2 (app-11)"));
+    assert_eq!(state.console().devices().len(), 11);
+    assert!(state.ttn_devices().is_empty());
+}
+
+/// (application, DevEUI) of 105 applications with one device each, and
+/// 101 more devices in the first, so applications and devices both
+/// take more than one page of 100
+fn paged_devices() -> Vec<(usize, String)> {
+    (0..105)
+        .chain(std::iter::repeat_n(0, 101))
+        .enumerate()
+        .map(|(index, app)| (app, format!("{:016X}", index)))
+        .collect()
+}
+
+#[test]
+fn imports_every_page_from_the_things_stack() {
+    let env = TestEnv::new();
+    for (app, dev_eui) in paged_devices() {
+        env.console.state().add_tts_device(TtsDevice {
+            app_id: format!("app-{:03}", app),
+            device_id: dev_eui.to_lowercase(),
+            name: String::new(),
+            dev_eui: Some(dev_eui),
+            join_eui: Some(APP_EUI.to_string()),
+            app_key: Some(APP_KEY.to_string()),
+        });
+    }
+
+    let imported = env.cli_with_env(
+        &[
+            "ttn",
+            "import-v3",
+            "--cluster-url",
+            &env.console.url(),
+            "--all-apps",
+            "--concurrency",
+            "8",
+        ],
+        &[("TTS_API_KEY", mock_console::TTS_API_KEY)],
+    );
+    assert!(imported.status.success(), "{}", stdout(&imported));
+    assert!(stdout(&imported).contains("206 created, 0 already existed, 0 failed"));
+    assert_eq!(env.console.state().console().devices().len(), 206);
+}
+
+#[test]
+fn imports_every_page_from_chirpstack() {
+    let env = TestEnv::new();
+    for (app, dev_eui) in paged_devices() {
+        env.console.state().add_chirpstack_device(ChirpstackDevice {
+            application_id: app.to_string(),
+            application_name: format!("app-{:03}", app),
+            dev_eui: dev_eui.clone(),
+            name: dev_eui,
+            device_profile_name: String::new(),
+            nwk_key: Some(APP_KEY.to_string()),
+        });
+    }
+
+    let imported = env.cli_with_env(
+        &[
+            "chirpstack",
+            "import",
+            "--server-url",
+            &env.console.url(),
+            "--app-eui",
+            APP_EUI,
+            "--all-applications",
+            "--concurrency",
+            "8",
+        ],
+        &[("CHIRPSTACK_API_TOKEN", mock_console::CHIRPSTACK_API_TOKEN)],
+    );
+    assert!(imported.status.success(), "{}", stdout(&imported));
+    assert!(stdout(&imported).contains("206 created, 0 already existed, 0 failed"));
+    assert_eq!(env.console.state().console().devices().len(), 206);
+}
+
+#[test]
+fn imports_exports_of_other_networks() {
+    let env = TestEnv::new();
+    let tts = format!(
+        "{{\"ids\":{{\"device_id\":\"otaa-1\",\"application_ids\":{{\"application_id\":\"greenhouse\"}},\
+         \"dev_eui\":\"{}\",\"join_eui\":\"{}\"}},\"root_keys\":{{\"app_key\":{{\"key\":\"{}\"}}}}}}\n\
+         {{\"ids\":{{\"device_id\":\"abp-1\",\"application_ids\":{{\"application_id\":\"greenhouse\"}}}}}}\n",
+        DEV_EUI, APP_EUI, APP_KEY
+    );
+    fs::write(env.dir.path().join("tts.json"), tts).unwrap();
+
+    let imported = env.cli(&[
+        "device",
+        "import",
+        "--from-file",
+        "tts.json",
+        "--format",
+        "ttn-v3",
+        "--label-with-app",
+        "--create-missing",
+    ]);
+    assert!(imported.status.success(), "{}", stdout(&imported));
+    assert!(stdout(&imported).contains("Skipping row 2 (greenhouse/abp-1)"));
+    assert!(stdout(&imported).contains("1 created, 0 already existed, 0 failed, 1 skipped"));
+    let report = fs::read_to_string(env.dir.path().join("tts.report.csv")).unwrap();
+    assert!(report.contains("greenhouse/abp-1"));
+    assert!(report.contains("no OTAA AppKey"));
+    assert_eq!(env.console.state().console().devices()[0].name(), "otaa-1");
+    assert_eq!(
+        env.console.state().console().labels()[0].name(),
+        "greenhouse"
+    );
+
+    let chirpstack = format!(
+        "DevEUI,Name,Application Name,NwkKey\n\
+         {},sensor,orchard,{}\n\
+         0000000000000001,second,orchard,{}\n",
+        DEV_EUI, APP_KEY, APP_KEY
+    );
+    fs::write(env.dir.path().join("chirpstack.csv"), chirpstack).unwrap();
+
+    let no_app_eui = env.cli(&[
+        "device",
+        "import",
+        "--file",
+        "chirpstack.csv",
+        "--format",
+        "chirpstack",
+    ]);
+    assert!(!no_app_eui.status.success());
+    assert!(stdout(&no_app_eui).contains("pass --app-eui"));
+
+    let imported = env.cli(&[
+        "device",
+        "import",
+        "--file",
+        "chirpstack.csv",
+        "--format",
+        "chirpstack",
+        "--app-eui",
+        APP_EUI,
+    ]);
+    assert!(imported.status.success(), "{}", stdout(&imported));
+    assert!(stdout(&imported).contains("1 created, 1 already existed, 0 failed\n"));
+    assert_eq!(env.console.state().console().devices().len(), 2);
+
+    fs::write(
+        env.dir.path().join("devices.csv"),
+        format!(
+            "app_eui,app_key,dev_eui,name\n{},{},{},plain\n",
+            APP_EUI, APP_KEY, DEV_EUI
+        ),
+    )
+    .unwrap();
+    let plain = env.cli(&[
+        "device",
+        "import",
+        "--file",
+        "devices.csv",
+        "--app-eui",
+        APP_EUI,
+    ]);
+    assert!(!plain.status.success());
+    assert!(stdout(&plain).contains("only apply to exports"));
+}
+
+#[test]
+fn dry_run_update_does_not_report_success() {
+    let env = TestEnv::new();
+    assert!(env.create_device(DEV_EUI, "sensor").status.success());
+    let device_id = env.first_device_id();
+
+    let updated = env.cli(&[
+        "--dry-run",
+        "device",
+        "update",
+        &device_id,
+        "--active",
+        "false",
+    ]);
+    assert!(updated.status.success(), "{}", stdout(&updated));
+    let output = stdout(&updated);
+    assert!(!output.contains("Device update successful"), "{}", output);
+    assert!(output.contains(&format!(
+        "PUT {}/api/v1/devices/{}",
+        env.console.url(),
+        device_id
+    )));
+    assert!(env.console.state().console().devices()[0].active());
+}
+
+#[test]
+fn refused_exports_leave_the_output_file_alone() {
+    let env = TestEnv::new();
+    let file = env.dir.path().join("devices.csv");
+    fs::write(&file, "kept\n").unwrap();
+
+    let refused = env.cli(&[
+        "device",
+        "export",
+        "--columns",
+        "dev_eui,app_key",
+        "--file",
+        "devices.csv",
+    ]);
+    assert!(!refused.status.success());
+    assert!(stdout(&refused).contains("requires --reveal-keys"));
+    assert_eq!(fs::read_to_string(&file).unwrap(), "kept\n");
+}
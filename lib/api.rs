@@ -0,0 +1,115 @@
+use super::*;
+use async_trait::async_trait;
+
+/// Device, label and device-label operations of the Console API.
+///
+/// `client::Client` implements this against the real server. Code written
+/// against the trait can be exercised with `testing::InMemoryConsole`
+/// (behind the `testing` feature) instead of a network.
+#[async_trait]
+pub trait ConsoleApi: Send + Sync {
+    async fn get_organization(&self) -> Result<Organization>;
+
+    async fn get_devices(&self) -> Result<Vec<Device>>;
+
+    async fn get_detailed_devices(&self) -> Result<Vec<DetailedDevice>>;
+
+    async fn get_device(&self, get_device: &GetDevice) -> Result<Device>;
+
+    async fn find_device(&self, selector: &DeviceSelector) -> Result<DetailedDevice>;
+
+    async fn get_device_by_id(&self, id: &str) -> Result<Device>;
+
+    async fn post_device(&self, new_device_request: &NewDevice) -> Result<Device>;
+
+    async fn update_device(&self, id: &str, update: &DeviceUpdate) -> Result;
+
+    async fn delete_device(&self, id: &str) -> Result;
+
+    async fn get_labels(&mut self) -> Result<Vec<Label>>;
+
+    async fn post_label(&self, new_label_request: &NewLabel) -> Result<Label>;
+
+    async fn rename_label(&self, id: &str, name: &str) -> Result;
+
+    async fn delete_label(&self, id: &str) -> Result;
+
+    async fn add_device_label(&self, device_id: String, device_label: &DeviceLabel) -> Result;
+
+    async fn remove_device_label(&self, device_id: String, device_label: &DeviceLabel) -> Result;
+
+    async fn find_labels(&mut self, label: &str) -> Result<Vec<Label>>;
+
+    async fn get_label_uuid(&mut self, label: &str, create_missing: bool) -> Result<String>;
+}
+
+#[async_trait]
+impl ConsoleApi for client::Client {
+    async fn get_organization(&self) -> Result<Organization> {
+        client::Client::get_organization(self).await
+    }
+
+    async fn get_devices(&self) -> Result<Vec<Device>> {
+        client::Client::get_devices(self).await
+    }
+
+    async fn get_detailed_devices(&self) -> Result<Vec<DetailedDevice>> {
+        client::Client::get_detailed_devices(self).await
+    }
+
+    async fn get_device(&self, get_device: &GetDevice) -> Result<Device> {
+        client::Client::get_device(self, get_device).await
+    }
+
+    async fn find_device(&self, selector: &DeviceSelector) -> Result<DetailedDevice> {
+        client::Client::find_device(self, selector).await
+    }
+
+    async fn get_device_by_id(&self, id: &str) -> Result<Device> {
+        client::Client::get_device_by_id(self, id).await
+    }
+
+    async fn post_device(&self, new_device_request: &NewDevice) -> Result<Device> {
+        client::Client::post_device(self, new_device_request).await
+    }
+
+    async fn update_device(&self, id: &str, update: &DeviceUpdate) -> Result {
+        client::Client::update_device(self, id, update).await
+    }
+
+    async fn delete_device(&self, id: &str) -> Result {
+        client::Client::delete_device(self, id).await
+    }
+
+    async fn get_labels(&mut self) -> Result<Vec<Label>> {
+        client::Client::get_labels(self).await
+    }
+
+    async fn post_label(&self, new_label_request: &NewLabel) -> Result<Label> {
+        client::Client::post_label(self, new_label_request).await
+    }
+
+    async fn rename_label(&self, id: &str, name: &str) -> Result {
+        client::Client::rename_label(self, id, name).await
+    }
+
+    async fn delete_label(&self, id: &str) -> Result {
+        client::Client::delete_label(self, id).await
+    }
+
+    async fn add_device_label(&self, device_id: String, device_label: &DeviceLabel) -> Result {
+        client::Client::add_device_label(self, device_id, device_label).await
+    }
+
+    async fn remove_device_label(&self, device_id: String, device_label: &DeviceLabel) -> Result {
+        client::Client::remove_device_label(self, device_id, device_label).await
+    }
+
+    async fn find_labels(&mut self, label: &str) -> Result<Vec<Label>> {
+        client::Client::find_labels(self, label).await
+    }
+
+    async fn get_label_uuid(&mut self, label: &str, create_missing: bool) -> Result<String> {
+        client::Client::get_label_uuid(self, label, create_missing).await
+    }
+}
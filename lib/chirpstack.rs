@@ -0,0 +1,203 @@
+//! ChirpStack (v3) REST API: applications, devices and their OTAA keys.
+//!
+//! ChirpStack matches joins on DevEUI alone and does not keep the JoinEUI
+//! devices were programmed with, so callers supply the AppEUI.
+
+use super::{
+    source_api::{Paging, SourceClient, StatusError},
+    AppEui, AppKey, DevEui, NewDevice, Secret,
+};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use std::{error::Error as stdError, fmt};
+
+pub use super::source_api::Result;
+
+pub struct Client {
+    api: SourceClient,
+}
+
+impl Client {
+    /// `server_url` is the ChirpStack Application Server, e.g.
+    /// `http://localhost:8080`, and `api_token` an API key it issued
+    pub fn new(server_url: &str, api_token: &str) -> Result<Client> {
+        Ok(Client {
+            api: SourceClient::new(server_url, "Grpc-Metadata-Authorization", api_token)?,
+        })
+    }
+
+    /// Every page of a listing; ChirpStack pages with `limit` and `offset`
+    async fn get_all<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
+        self.api
+            .get_all(path, Paging::Offset, |page: Page<T>| page.result)
+            .await
+    }
+
+    /// Applications the token can see, optionally of one organization only
+    pub async fn get_applications(
+        &self,
+        organization_id: Option<&str>,
+    ) -> Result<Vec<Application>> {
+        match organization_id {
+            Some(id) => {
+                self.get_all(&format!("api/applications?organizationID={}", id))
+                    .await
+            }
+            None => self.get_all("api/applications").await,
+        }
+    }
+
+    pub async fn get_devices(&self, application_id: &str) -> Result<Vec<Device>> {
+        self.get_all(&format!("api/devices?applicationID={}", application_id))
+            .await
+    }
+
+    /// The device with its OTAA keys. ABP devices have none.
+    pub async fn get_keys(&self, device: &Device) -> Result<Device> {
+        let path = format!("api/devices/{}/keys", device.dev_eui);
+        let keys = match self.api.get::<DeviceKeysResponse>(&path).await {
+            Ok(response) => Some(response.device_keys),
+            Err(err) if StatusError::is_not_found(err.as_ref()) => None,
+            Err(err) => return Err(err),
+        };
+        Ok(Device {
+            keys,
+            ..device.clone()
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Page<T> {
+    #[serde(default = "Vec::new")]
+    result: Vec<T>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct Application {
+    id: String,
+    name: String,
+}
+
+impl Application {
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    #[serde(rename = "devEUI")]
+    dev_eui: DevEui,
+    name: String,
+    #[serde(rename = "applicationID")]
+    application_id: String,
+    #[serde(default)]
+    device_profile_name: String,
+    #[serde(skip)]
+    keys: Option<DeviceKeys>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceKeysResponse {
+    device_keys: DeviceKeys,
+}
+
+// For LoRaWAN 1.0.x devices ChirpStack keeps the AppKey in `nwkKey`;
+// `appKey` only holds it for LoRaWAN 1.1 devices
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DeviceKeys {
+    #[serde(default)]
+    nwk_key: Option<Secret<String>>,
+    #[serde(default)]
+    app_key: Option<Secret<String>>,
+}
+
+impl Device {
+    /// Requires the keys, see `Client::get_keys`. `app_eui` is the JoinEUI
+    /// the devices were programmed with.
+    pub fn derive_new_device_request(&self, app_eui: AppEui) -> Result<NewDevice> {
+        let keys = self.keys.as_ref().ok_or(Error::NoAppKey)?;
+        let key = [&keys.nwk_key, &keys.app_key]
+            .iter()
+            .filter_map(|key| key.as_ref())
+            .map(Secret::expose_secret)
+            .find(|key| !key.is_empty())
+            .ok_or(Error::NoAppKey)?;
+        let app_key: AppKey = key.parse().map_err(|_| Error::NoAppKey)?;
+        Ok(NewDevice::new(
+            app_eui,
+            app_key,
+            self.dev_eui,
+            self.name.clone(),
+        ))
+    }
+
+    pub fn dev_eui(&self) -> &DevEui {
+        &self.dev_eui
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn application_id(&self) -> &String {
+        &self.application_id
+    }
+
+    pub fn device_profile_name(&self) -> &String {
+        &self.device_profile_name
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    NoAppKey,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoAppKey => write!(f, "Device has no OTAA keys (ABP devices are not supported)"),
+        }
+    }
+}
+
+impl stdError for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_fetched_keys_out_of_debug_output() {
+        let key = "2b7e151628aed2a6abf7158809cf4f3c";
+        let response: DeviceKeysResponse = serde_json::from_value(serde_json::json!({
+            "deviceKeys": { "nwkKey": key, "appKey": "" }
+        }))
+        .unwrap();
+        let device = Device {
+            dev_eui: DevEui::from_bytes([1; 8]),
+            name: "sensor".to_string(),
+            application_id: "1".to_string(),
+            device_profile_name: String::new(),
+            keys: Some(response.device_keys),
+        };
+        assert!(!format!("{:?}", device).contains(key));
+
+        let request = device
+            .derive_new_device_request(AppEui::from_bytes([2; 8]))
+            .unwrap();
+        assert_eq!(
+            request.app_key().expose_secret(),
+            &key.parse::<AppKey>().unwrap()
+        );
+    }
+}
@@ -1,17 +1,35 @@
 use super::*;
-use reqwest::Client as ReqwestClient;
+pub use crate::retry::RetryPolicy;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt, TryStreamExt,
+};
+use reqwest::{header::RETRY_AFTER, Client as ReqwestClient, Method, Response};
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Config {
     key: String,
     base_url: String,
     request_timeout: u64,
+    #[serde(default = "default_page_size")]
+    page_size: u32,
+    #[serde(default)]
+    label_match: LabelMatch,
+    // tables must follow plain values when written as TOML
+    #[serde(default)]
+    retry: RetryPolicy,
 }
 
 const DEFAULT_BASE_URL: &str = "https://console.helium.com";
 const DEFAULT_TIMEOUT: u64 = 120;
+const DEFAULT_PAGE_SIZE: u32 = 100;
+
+fn default_page_size() -> u32 {
+    DEFAULT_PAGE_SIZE
+}
 
 impl Config {
     pub fn new(key: String) -> Config {
@@ -23,6 +41,118 @@ impl Config {
             key,
             base_url: url.to_string(),
             request_timeout: DEFAULT_TIMEOUT,
+            retry: RetryPolicy::default(),
+            page_size: DEFAULT_PAGE_SIZE,
+            label_match: LabelMatch::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Config {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_key(mut self, key: String) -> Config {
+        self.key = key;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Config {
+        self.base_url = base_url.to_string();
+        self
+    }
+
+    pub fn with_request_timeout(mut self, request_timeout: u64) -> Config {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    pub fn with_page_size(mut self, page_size: u32) -> Config {
+        self.page_size = page_size;
+        self
+    }
+
+    /// How label names are matched; case-insensitive by default
+    pub fn with_label_match(mut self, label_match: LabelMatch) -> Config {
+        self.label_match = label_match;
+        self
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn request_timeout(&self) -> u64 {
+        self.request_timeout
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    pub fn label_match(&self) -> LabelMatch {
+        self.label_match
+    }
+
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Checks what `Client::new` needs: a 32-byte base64 key and an
+    /// absolute http(s) base URL
+    pub fn validate(&self) -> Result {
+        match base64::decode(&self.key) {
+            Ok(key) if key.len() == 32 => (),
+            _ => return Err(ConsoleError::InvalidApiKey),
+        }
+        match url::Url::parse(&self.base_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(()),
+            _ => Err(ConsoleError::InvalidBaseUrl(self.base_url.clone())),
+        }
+    }
+}
+
+/// One page of a listing. Servers that don't paginate answer with a
+/// bare array, which is treated as the one and only page.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Page<T> {
+    Paginated {
+        data: Vec<T>,
+        #[serde(default)]
+        next_cursor: Option<String>,
+        #[serde(default)]
+        next_page: Option<u32>,
+    },
+    Unpaginated(Vec<T>),
+}
+
+/// Query parameter selecting the next page to fetch
+enum PageRequest {
+    First,
+    Cursor(String),
+    Page(u32),
+}
+
+impl<T> Page<T> {
+    fn into_parts(self) -> (Vec<T>, Option<PageRequest>) {
+        match self {
+            Page::Paginated {
+                data,
+                next_cursor: Some(cursor),
+                ..
+            } => (data, Some(PageRequest::Cursor(cursor))),
+            Page::Paginated {
+                data,
+                next_page: Some(page),
+                ..
+            } => (data, Some(PageRequest::Page(page))),
+            Page::Paginated { data, .. } => (data, None),
+            Page::Unpaginated(data) => (data, None),
         }
     }
 }
@@ -32,266 +162,384 @@ pub struct Client {
     base_url: String,
     key: String,
     client: ReqwestClient,
+    retry: RetryPolicy,
+    page_size: u32,
     // map label to uuid
     labels: HashMap<String, String>,
+    label_match: LabelMatch,
+    // when set, non-GET requests are recorded instead of sent
+    dry_run: Option<DryRun>,
 }
 
 impl Client {
     pub fn new(config: Config) -> Result<Client> {
+        config.validate()?;
+
         let timeout = config.request_timeout;
         let client = ReqwestClient::builder()
             .timeout(Duration::from_secs(timeout))
-            .build()?;
-
-        // verify API key
-        let key = base64::decode(&config.key)?;
-        if key.len() != 32 {
-            println!("Invalid key in config file");
-            return Err(Error::InvalidApiKey.into());
-        }
+            .build()
+            .map_err(|source| ConsoleError::Transport {
+                path: config.base_url.clone(),
+                source,
+            })?;
 
         Ok(Client {
             base_url: config.base_url,
             key: config.key,
             client,
+            retry: config.retry,
+            page_size: config.page_size,
             labels: HashMap::new(),
+            label_match: config.label_match,
+            dry_run: None,
         })
     }
 
-    fn get(&self, path: &str) -> Result<reqwest::RequestBuilder> {
-        Ok(self
-            .client
-            .get(format!("{}/{}", self.base_url, path).as_str())
-            .header("key", self.key.as_str()))
+    /// Records POST, PUT and DELETE requests in `dry_run` instead of
+    /// sending them. GET requests still go to the server.
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Client {
+        self.dry_run = Some(dry_run);
+        self
     }
 
-    fn post(&self, path: &str) -> Result<reqwest::RequestBuilder> {
-        Ok(self
-            .client
-            .post(format!("{}/{}", self.base_url, path).as_str())
-            .header("key", self.key.as_str()))
+    /// Sends a request, retrying idempotent methods according to the retry policy
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<Response> {
+        if let Some(dry_run) = &self.dry_run {
+            if method != Method::GET {
+                let response = dry_run::response_body(body.as_ref());
+                dry_run.record(method, format!("{}/{}", self.base_url, path), body);
+                return Ok(http::Response::new(response).into());
+            }
+        }
+
+        let idempotent = method == Method::GET || method == Method::DELETE;
+        let mut attempt = 1;
+        loop {
+            match self.send_once(method.clone(), path, body.as_ref()).await {
+                Err(err) if idempotent && self.retry.should_retry(attempt, &err) => {
+                    tokio::time::delay_for(self.retry.delay(attempt, &err)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
     }
 
-    fn delete(&self, path: &str) -> Result<reqwest::RequestBuilder> {
-        Ok(self
+    /// Sends a request once and maps any non-success status onto a `ConsoleError`
+    async fn send_once(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<Response> {
+        let mut request = self
             .client
-            .delete(format!("{}/{}", self.base_url, path).as_str())
-            .header("key", self.key.as_str()))
-    }
+            .request(
+                method.clone(),
+                format!("{}/{}", self.base_url, path).as_str(),
+            )
+            .header("key", self.key.as_str());
+        if let Some(body) = body {
+            request = request.json(body);
+        }
 
-    pub async fn get_detailed_devices(&self) -> Result<Vec<DetailedDevice>> {
-        let request = self.get("api/v1/devices")?;
-        let response = request.send().await?;
-        if response.status() == 200 {
-            let body = response.text().await.unwrap();
-            let devices: Vec<DetailedDevice> = serde_json::from_str(&body)?;
-            Ok(devices)
-        } else if response.status() == 401 {
-            let body = response.text().await.unwrap();
-            println!("{}", body);
-            Err(Error::UnauthorizedApi.into())
+        let response = request
+            .send()
+            .await
+            .map_err(|source| ConsoleError::Transport {
+                path: path.to_string(),
+                source,
+            })?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
         } else {
-            Err(Error::HttpErrorApi.into())
+            // only the delta-seconds form of Retry-After is honoured
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs);
+            let body = response.text().await.unwrap_or_default();
+            Err(ConsoleError::from_response(
+                ApiError {
+                    method,
+                    path: path.to_string(),
+                    status,
+                    body,
+                },
+                retry_after,
+            ))
         }
     }
 
+    async fn decode<T: DeserializeOwned>(path: &str, response: Response) -> Result<T> {
+        let body = response
+            .text()
+            .await
+            .map_err(|source| ConsoleError::Transport {
+                path: path.to_string(),
+                source,
+            })?;
+        serde_json::from_str(&body).map_err(|source| ConsoleError::Decode {
+            path: path.to_string(),
+            body,
+            source,
+        })
+    }
+
+    fn to_body<B: Serialize>(path: &str, body: &B) -> Result<serde_json::Value> {
+        serde_json::to_value(body).map_err(|source| ConsoleError::Encode {
+            path: path.to_string(),
+            source,
+        })
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.send(Method::GET, path, None).await?;
+        Self::decode(path, response).await
+    }
+
+    async fn post<B: Serialize, T: DeserializeOwned>(&self, path: &str, body: &B) -> Result<T> {
+        let body = Self::to_body(path, body)?;
+        let response = self.send(Method::POST, path, Some(body)).await?;
+        Self::decode(path, response).await
+    }
+
+    async fn post_no_content<B: Serialize>(&self, path: &str, body: &B) -> Result {
+        let body = Self::to_body(path, body)?;
+        self.send(Method::POST, path, Some(body)).await?;
+        Ok(())
+    }
+
+    async fn put_no_content<B: Serialize>(&self, path: &str, body: &B) -> Result {
+        let body = Self::to_body(path, body)?;
+        self.send(Method::PUT, path, Some(body)).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str) -> Result {
+        self.send(Method::DELETE, path, None).await?;
+        Ok(())
+    }
+
+    /// Streams every record of a listing endpoint, one page at a time
+    fn paginate<'a, T>(&'a self, path: &'a str) -> BoxStream<'a, Result<T>>
+    where
+        T: DeserializeOwned + Send + 'a,
+    {
+        stream::try_unfold(Some(PageRequest::First), move |request| async move {
+            let query = match request {
+                None => return Ok(None),
+                Some(PageRequest::First) => format!("page_size={}", self.page_size),
+                Some(PageRequest::Cursor(cursor)) => format!(
+                    "page_size={}&cursor={}",
+                    self.page_size,
+                    url::form_urlencoded::byte_serialize(cursor.as_bytes()).collect::<String>()
+                ),
+                Some(PageRequest::Page(page)) => {
+                    format!("page_size={}&page={}", self.page_size, page)
+                }
+            };
+            let page: Page<T> = self.get(format!("{}?{}", path, query).as_str()).await?;
+            let (records, next) = page.into_parts();
+            Ok(Some((stream::iter(records.into_iter().map(Ok)), next)))
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    pub fn stream_detailed_devices(&self) -> BoxStream<'_, Result<DetailedDevice>> {
+        self.paginate("api/v1/devices")
+    }
+
+    pub fn stream_devices(&self) -> BoxStream<'_, Result<Device>> {
+        self.paginate("api/v1/devices")
+    }
+
+    pub fn stream_labels(&self) -> BoxStream<'_, Result<Label>> {
+        self.paginate("api/v1/labels")
+    }
+
+    pub async fn get_detailed_devices(&self) -> Result<Vec<DetailedDevice>> {
+        self.stream_detailed_devices().try_collect().await
+    }
+
     pub async fn get_devices(&self) -> Result<Vec<Device>> {
-        let request = self.get("api/v1/devices")?;
-        let response = request.send().await?;
-        if response.status() == 200 {
-            let body = response.text().await.unwrap();
-            let devices: Vec<Device> = serde_json::from_str(&body)?;
-            Ok(devices)
-        } else if response.status() == 401 {
-            let body = response.text().await.unwrap();
-            println!("{}", body);
-            Err(Error::UnauthorizedApi.into())
-        } else {
-            Err(Error::HttpErrorApi.into())
-        }
+        self.stream_devices().try_collect().await
+    }
+
+    /// Organization the API key belongs to
+    pub async fn get_organization(&self) -> Result<Organization> {
+        self.get("api/v1/organization").await
     }
 
+    /// Looks a device up by its credentials. The device list is searched
+    /// locally so the AppKey never ends up in a URL, which costs a full
+    /// listing per call; use a `DeviceIndex` for repeated lookups.
     pub async fn get_device(&self, get_device: &GetDevice) -> Result<Device> {
-        let request = self.get(
-            format!(
-                "api/v1/devices?dev_eui={}&app_eui={}&app_key={}",
-                get_device.dev_eui(),
-                get_device.app_eui(),
-                get_device.app_key()
-            )
-            .as_str(),
-        )?;
-        let response = request.send().await?;
-        if response.status() == 200 {
-            let body = response.text().await.unwrap();
-            let devices: Device = serde_json::from_str(&body)?;
-            Ok(devices)
-        } else if response.status() == 401 {
-            let body = response.text().await.unwrap();
-            println!("{}", body);
-            Err(Error::UnauthorizedApi.into())
-        } else {
-            Err(Error::HttpErrorApi.into())
-        }
+        let device = self
+            .find_device(&DeviceSelector::Credentials(get_device.clone()))
+            .await?;
+        Ok(Device::from(&device))
+    }
+
+    /// The single device matching `selector`; `NoMatchingDevice` or
+    /// `AmbiguousDevice` otherwise
+    pub async fn find_device(&self, selector: &DeviceSelector) -> Result<DetailedDevice> {
+        selector.pick(self.get_detailed_devices().await?)
     }
 
     pub async fn get_device_by_id(&self, id: &str) -> Result<Device> {
-        let request = self.get(format!("api/v1/devices/{}", id).as_str())?;
-        let response = request.send().await?;
-        if response.status() == 200 {
-            let body = response.text().await.unwrap();
-            let device: Device = serde_json::from_str(&body)?;
-            Ok(device)
-        } else if response.status() == 401 {
-            let body = response.text().await.unwrap();
-            println!("{}", body);
-            Err(Error::UnauthorizedApi.into())
-        } else {
-            Err(Error::HttpErrorApi.into())
-        }
+        self.get(format!("api/v1/devices/{}", id).as_str()).await
     }
 
+    /// Creates a device. POST is not idempotent, so failed attempts are only
+    /// retried when the retry policy opts in with `retry_device_creation`.
+    /// A 422 on a retry means an earlier attempt was applied after all, and
+    /// resolves to the existing device.
     pub async fn post_device(&self, new_device_request: &NewDevice) -> Result<Device> {
-        let request = self.post("api/v1/devices")?.json(&new_device_request);
-        let response = request.send().await?;
-        if response.status() == 201 {
-            let body = response.text().await?;
-            let device: Device = serde_json::from_str(&body)?;
-            Ok(device)
-        } else if response.status() == 401 {
-            let body = response.text().await.unwrap();
-            println!("{}", body);
-            Err(Error::UnauthorizedApi.into())
-        } else if response.status() == 422 {
-            Err(Error::NewDevice422.into())
-        } else {
-            Err(Error::NewDeviceApi.into())
+        if !self.retry.retry_device_creation {
+            return self.post("api/v1/devices", new_device_request).await;
         }
-    }
 
-    pub async fn delete_device(&self, id: &str) -> Result<()> {
-        let request = self.delete(format!("api/v1/devices/{}", id).as_str())?;
-        let response = request.send().await?;
-        if response.status() == 200 {
-            println!("Device delete successful");
-            let _response_body = response.text().await?;
-            Ok(())
-        } else if response.status() == 401 {
-            let body = response.text().await.unwrap();
-            println!("{}", body);
-            Err(Error::UnauthorizedApi.into())
-        } else if response.status() == 404 {
-            println!("Device not found. Delete failed.");
-            Ok(())
-        } else {
-            Err(Error::HttpErrorApi.into())
+        let mut attempt = 1;
+        loop {
+            match self.post("api/v1/devices", new_device_request).await {
+                Err(ConsoleError::Conflict(_)) if attempt > 1 => {
+                    return self.get_device(&new_device_request.into()).await;
+                }
+                Err(err) if self.retry.should_retry(attempt, &err) => {
+                    tokio::time::delay_for(self.retry.delay(attempt, &err)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
         }
     }
 
+    pub async fn update_device(&self, id: &str, update: &DeviceUpdate) -> Result {
+        self.put_no_content(format!("api/v1/devices/{}", id).as_str(), update)
+            .await
+    }
+
+    pub async fn delete_device(&self, id: &str) -> Result {
+        self.delete(format!("api/v1/devices/{}", id).as_str()).await
+    }
+
     /// Labels
     pub async fn get_labels(&mut self) -> Result<Vec<Label>> {
-        let request = self.get("api/v1/labels")?;
-        let response = request.send().await?;
-        let body = response.text().await.unwrap();
-        let labels: Vec<Label> = serde_json::from_str(&body)?;
+        let labels: Vec<Label> = self.stream_labels().try_collect().await?;
 
+        self.labels.clear();
         for label in &labels {
             self.labels.insert(label.name().clone(), label.id().clone());
         }
         Ok(labels)
     }
 
-    pub async fn post_label(&self, new_label_request: &NewLabel) -> Result<Label> {
-        let request = self.post("api/v1/labels")?.json(&new_label_request);
-        let response = request.send().await?;
-        if response.status() == 201 {
-            let body = response.text().await?;
-            let label: Label = serde_json::from_str(&body)?;
-            Ok(label)
-        } else if response.status() == 422 {
-            Err(Error::NewLabel422.into())
-        } else {
-            Err(Error::NewLabelApi.into())
-        }
+    /// Labels matching a UUID or name in the cache filled by `get_labels`
+    fn cached_labels(&self, label: &str) -> Vec<Label> {
+        let cached = self.labels.iter().map(|(name, id)| Label {
+            id: id.clone(),
+            name: name.clone(),
+        });
+        self.label_match.select(label, cached)
     }
 
-    pub async fn delete_label(&self, id: &str) -> Result<()> {
-        let request = self.delete(format!("api/v1/labels/{}", id).as_str())?;
-        let response = request.send().await?;
-        if response.status() == 200 {
-            println!("Label delete successful");
-        } else if response.status() == 404 {
-            println!("Label not found. Delete failed.");
+    /// Every label a UUID or name refers to under the client's `LabelMatch`
+    /// policy, with an identical name first. More than one label means the
+    /// name is ambiguous across case variants. Labels are fetched on first
+    /// use and again when nothing matches, in case the label is new.
+    pub async fn find_labels(&mut self, label: &str) -> Result<Vec<Label>> {
+        let found = self.cached_labels(label);
+        if !found.is_empty() {
+            return Ok(found);
         }
-        let _response_body = response.text().await?;
-        Ok(())
+        self.get_labels().await?;
+        Ok(self.cached_labels(label))
+    }
+
+    /// The label a UUID or name refers to; see `find_labels`. A name that
+    /// matches several labels, none of them identically, is an
+    /// `AmbiguousLabel` error rather than a guess.
+    pub async fn find_label(&mut self, label: &str) -> Result<Option<Label>> {
+        let found = self.find_labels(label).await?;
+        self.label_match.select_one(label, found)
+    }
+
+    /// Label names keyed by UUID, from the labels cached by `get_labels`
+    pub fn label_names(&self) -> HashMap<String, String> {
+        self.labels
+            .iter()
+            .map(|(name, id)| (id.clone(), name.clone()))
+            .collect()
+    }
+
+    pub async fn post_label(&self, new_label_request: &NewLabel) -> Result<Label> {
+        self.post("api/v1/labels", new_label_request).await
+    }
+
+    pub async fn rename_label(&self, id: &str, name: &str) -> Result {
+        self.put_no_content(
+            format!("api/v1/labels/{}", id).as_str(),
+            &NewLabel::from_string(name),
+        )
+        .await
+    }
+
+    pub async fn delete_label(&self, id: &str) -> Result {
+        self.delete(format!("api/v1/labels/{}", id).as_str()).await
     }
 
     /// Device Label
-    pub async fn add_device_label(
-        &self,
-        device_id: String,
-        device_label: &DeviceLabel,
-    ) -> Result<()> {
-        let request = self
-            .post(format!("api/v1/devices/{:}/labels", device_id).as_str())?
-            .json(&device_label);
-        let response = request.send().await?;
-        if response.status() == 201 || response.status() == 200 {
-            let body = response.text().await?;
-            println!("{}", body);
-            Ok(())
-        } else {
-            let body = response.text().await?;
-            println!("{}", body);
-            Err(Error::NewDeviceLabelApi.into())
-        }
+    pub async fn add_device_label(&self, device_id: String, device_label: &DeviceLabel) -> Result {
+        self.post_no_content(
+            format!("api/v1/devices/{:}/labels", device_id).as_str(),
+            device_label,
+        )
+        .await
     }
 
     pub async fn remove_device_label(
         &self,
         device_id: String,
         device_label: &DeviceLabel,
-    ) -> Result<()> {
-        let request = self.delete(
+    ) -> Result {
+        self.delete(
             format!(
                 "api/v1/devices/{:}/labels/{:}",
                 device_id,
                 device_label.get_uuid()
             )
             .as_str(),
-        )?;
-        let response = request.send().await?;
-        if response.status() == 200 {
-            let body = response.text().await?;
-            println!("{:}", body);
-        } else if response.status() == 404 {
-            println!("Device label not found. Delete failed.");
-        }
-        Ok(())
+        )
+        .await
     }
 
-    pub async fn get_label_uuid(&mut self, device_label: &str) -> Result<String> {
-        let label_upper = device_label.to_uppercase();
-
-        // we probably haven't fetched labels if length is 0
-        if self.labels.is_empty() {
-            self.get_labels().await?;
+    /// UUID of the label with this name or UUID. A missing label is created
+    /// when `create_missing` is set, named as the `LabelMatch` policy says,
+    /// and is an `UnknownLabel` error otherwise. Ambiguous names are refused
+    /// as in `find_label`.
+    pub async fn get_label_uuid(&mut self, label: &str, create_missing: bool) -> Result<String> {
+        if let Some(found) = self.find_label(label).await? {
+            return Ok(found.id);
         }
-
-        // if the uuid still doesn't exist even after an intial fetch
-        // create it
-        if !self.labels.contains_key(&label_upper) {
-            println!("Label does not exist. Creating label: {}", label_upper);
-            let request = NewLabel::from_string(&label_upper);
-            let label = self.post_label(&request).await?;
-            self.labels.insert(label.name().clone(), label.id().clone());
-        }
-        // at this point, the above either errored or the label exists
-        if let Some(id) = self.labels.get(&label_upper) {
-            Ok(id.clone())
-        } else {
-            panic!("Label should exist here. Error out.")
+        if !create_missing || validate_uuid_input(label).is_ok() {
+            return Err(ConsoleError::UnknownLabel(label.to_string()));
         }
+        let name = self.label_match.normalize(label);
+        let created = self.post_label(&NewLabel::from_string(&name)).await?;
+        self.labels
+            .insert(created.name().clone(), created.id().clone());
+        Ok(created.id)
     }
 }
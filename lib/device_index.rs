@@ -0,0 +1,78 @@
+//! Credential lookups against one fetched device listing.
+
+use super::*;
+use futures::lock::Mutex;
+
+/// Resolves devices by their credentials from a listing fetched on first
+/// use. A run that resolves many 422 conflicts, such as an import of
+/// devices that already exist, then pages through the organization once
+/// instead of once per device. A miss fetches the listing again, in case
+/// the device was created after it was fetched.
+#[derive(Debug, Default)]
+pub struct DeviceIndex {
+    devices: Mutex<Option<Vec<DetailedDevice>>>,
+}
+
+impl DeviceIndex {
+    pub fn new() -> DeviceIndex {
+        DeviceIndex::default()
+    }
+
+    /// Same result as `ConsoleApi::get_device`
+    pub async fn get_device<C: ConsoleApi>(
+        &self,
+        client: &C,
+        get_device: &GetDevice,
+    ) -> Result<Device> {
+        let selector = DeviceSelector::Credentials(get_device.clone());
+        let mut devices = self.devices.lock().await;
+        if let Some(device) = devices
+            .iter()
+            .flatten()
+            .find(|device| selector.matches(device))
+        {
+            return Ok(Device::from(device));
+        }
+        let fetched = client.get_detailed_devices().await?;
+        let found = fetched
+            .iter()
+            .find(|device| selector.matches(device))
+            .map(Device::from);
+        *devices = Some(fetched);
+        found.ok_or_else(|| ConsoleError::NoMatchingDevice(selector.to_string()))
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::InMemoryConsole;
+    use futures::executor::block_on;
+
+    fn new_device(dev_eui: u8) -> NewDevice {
+        NewDevice::new(
+            AppEui::from_bytes([1; 8]),
+            AppKey::from_bytes([2; 16]),
+            DevEui::from_bytes([dev_eui; 8]),
+            format!("device-{}", dev_eui),
+        )
+    }
+
+    #[test]
+    fn finds_devices_created_after_the_listing() {
+        let console = InMemoryConsole::new();
+        let index = DeviceIndex::new();
+        let first = block_on(console.post_device(&new_device(1))).unwrap();
+        let found = block_on(index.get_device(&console, &(&new_device(1)).into())).unwrap();
+        assert_eq!(found.id(), first.id());
+
+        let second = block_on(console.post_device(&new_device(2))).unwrap();
+        let found = block_on(index.get_device(&console, &(&new_device(2)).into())).unwrap();
+        assert_eq!(found.id(), second.id());
+
+        assert!(matches!(
+            block_on(index.get_device(&console, &(&new_device(3)).into())),
+            Err(ConsoleError::NoMatchingDevice(_))
+        ));
+    }
+}
@@ -0,0 +1,82 @@
+//! Interception of mutating requests for `--dry-run`.
+//!
+//! A client with a `DryRun` attached sends GET requests as usual, so
+//! validations and lookups still run against the server, but records
+//! every other request instead of sending it.
+
+use crate::secret;
+use reqwest::Method;
+use serde_json::Value;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// A request that was recorded instead of sent
+#[derive(Clone, Debug)]
+pub struct PlannedRequest {
+    pub method: Method,
+    pub url: String,
+    pub body: Option<Value>,
+}
+
+impl fmt::Display for PlannedRequest {
+    /// Keys in the body are masked unless printed inside `secret::reveal`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.url)?;
+        if let Some(body) = &self.body {
+            let mut body = body.clone();
+            if !secret::is_revealed() {
+                secret::mask(&mut body);
+            }
+            write!(f, "\n{}", body)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared log of planned requests. Clones record into the same log, so
+/// one `DryRun` can be handed to several clients.
+#[derive(Clone, Debug, Default)]
+pub struct DryRun {
+    requests: Arc<Mutex<Vec<PlannedRequest>>>,
+}
+
+impl DryRun {
+    pub fn new() -> DryRun {
+        DryRun::default()
+    }
+
+    pub(crate) fn record(&self, method: Method, url: String, body: Option<Value>) {
+        self.requests
+            .lock()
+            .expect("dry run log poisoned")
+            .push(PlannedRequest { method, url, body });
+    }
+
+    /// Every request recorded so far, leaving the log empty
+    pub fn take(&self) -> Vec<PlannedRequest> {
+        std::mem::take(&mut *self.requests.lock().expect("dry run log poisoned"))
+    }
+}
+
+/// Stand-in for the server's answer to a request that was not sent: the
+/// request body with a nil UUID as `id`, which decodes as the created
+/// device or label
+pub(crate) fn response_body(body: Option<&Value>) -> String {
+    let nil = uuid::Uuid::nil().to_hyphenated().to_string();
+    match body {
+        Some(Value::Object(fields)) => {
+            let mut fields = fields.clone();
+            fields
+                .entry("id")
+                .or_insert_with(|| Value::String(nil.clone()));
+            fields
+                .entry("organization_id")
+                .or_insert_with(|| Value::String(nil.clone()));
+            fields.entry("oui").or_insert_with(|| Value::from(0));
+            Value::Object(fields).to_string()
+        }
+        _ => "{}".to_string(),
+    }
+}
@@ -1,80 +1,194 @@
-use std::{fmt, str};
+use reqwest::{Method, StatusCode};
+use std::{fmt, time::Duration};
 
-#[derive(Debug, Clone)]
-pub enum Error {
+/// Everything that can go wrong when talking to the Console API
+/// or when building a request for it
+#[derive(Debug)]
+pub enum ConsoleError {
     InvalidAppEui,
     InvalidAppKey,
     InvalidDevEui,
     InvalidApiKey,
-    InvalidUuid,
-    NewDevice422,
-    NewDeviceApi,
-    NewLabel422,
-    NewLabelApi,
-    NewDeviceLabelApi,
-    UnauthorizedApi,
-    HttpErrorApi,
+    InvalidBaseUrl(String),
+    InvalidUuid(String),
+    /// No label with this name or UUID exists
+    UnknownLabel(String),
+    /// No device matches a `DeviceSelector`
+    NoMatchingDevice(String),
+    /// More than one device matches a `DeviceSelector`
+    AmbiguousDevice {
+        selector: String,
+        devices: Vec<String>,
+    },
+    /// A label name matches several case variants, none of them identical
+    AmbiguousLabel {
+        label: String,
+        labels: Vec<String>,
+    },
+    /// 404: the requested record does not exist
+    NotFound(ApiError),
+    /// 409 or 422: a record with identical credentials or name already exists
+    Conflict(ApiError),
+    /// 401: the API key was rejected
+    Unauthorized(ApiError),
+    /// 429: too many requests, with the server's `Retry-After` hint
+    RateLimited {
+        error: ApiError,
+        retry_after: Option<Duration>,
+    },
+    /// 5xx: the server failed to handle the request
+    ServerError(ApiError),
+    /// Any other non-success status
+    UnexpectedStatus(ApiError),
+    /// A success response whose body could not be deserialized
+    Decode {
+        path: String,
+        body: String,
+        source: serde_json::Error,
+    },
+    /// A request body that could not be serialized
+    Encode {
+        path: String,
+        source: serde_json::Error,
+    },
+    /// The request never produced a response (connection, timeout, TLS...)
+    Transport {
+        path: String,
+        source: reqwest::Error,
+    },
+}
+
+impl ConsoleError {
+    /// Maps a non-success response onto the matching variant
+    pub fn from_response(error: ApiError, retry_after: Option<Duration>) -> ConsoleError {
+        match error.status.as_u16() {
+            401 => ConsoleError::Unauthorized(error),
+            404 => ConsoleError::NotFound(error),
+            409 | 422 => ConsoleError::Conflict(error),
+            429 => ConsoleError::RateLimited { error, retry_after },
+            500..=599 => ConsoleError::ServerError(error),
+            _ => ConsoleError::UnexpectedStatus(error),
+        }
+    }
+
+    /// The server response behind the error, if there was one
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            ConsoleError::NotFound(error)
+            | ConsoleError::Conflict(error)
+            | ConsoleError::Unauthorized(error)
+            | ConsoleError::RateLimited { error, .. }
+            | ConsoleError::ServerError(error)
+            | ConsoleError::UnexpectedStatus(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    /// HTTP status returned by the server, if there was a response
+    pub fn status(&self) -> Option<StatusCode> {
+        self.api_error().map(|error| error.status)
+    }
+}
+
+/// A non-success response from the Console API
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub method: Method,
+    pub path: String,
+    pub status: StatusCode,
+    pub body: String,
 }
 
-impl fmt::Display for Error {
+impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let msg = match self {
-            Error::InvalidAppEui => {
+        write!(f, "{} {} returned {}", self.method, self.path, self.status)?;
+        if !self.body.is_empty() {
+            write!(f, ": {}", self.body)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConsoleError::InvalidAppEui => write!(
+                f,
                 "Invalid AppEui input. Must be 8 bytes represented in hex (\"0123456789ABCDEF\")"
-            }
-            Error::InvalidAppKey => {
+            ),
+            ConsoleError::InvalidAppKey => write!(
+                f,
                 "Invalid AppKey input. Must be 16 bytes represented in hex (\"0123456789ABCDEF0123456789ABCDEF\")"
-            }
-            Error::InvalidDevEui => {
+            ),
+            ConsoleError::InvalidDevEui => write!(
+                f,
                 "Invalid DevEui input. Must be 8 bytes represented in hex (\"0123456789ABCDEF\")"
+            ),
+            ConsoleError::InvalidApiKey => {
+                write!(f, "Invalid Api Key. Must be 32 bytes represented in base64")
             }
-            Error::InvalidApiKey => {
-                "Invalid Api Key. Must be 32 bytes represented in base64"
-            }
-            Error::InvalidUuid => {
-                "Invalid UUID input. Expected in hyphenated form \"00000000-0000-0000-0000-000000000000\""
-            }
-            Error::NewDevice422 => {
-                "Failed Creating Device! Device with identical credentials already exists"
-            }
-            Error::NewDeviceApi => {
-                "Failed Creating Device! Unknown server error"
+            ConsoleError::InvalidBaseUrl(input) => {
+                write!(f, "Invalid base URL. Expected e.g. https://console.helium.com [input: {}]", input)
             }
-            Error::NewLabel422 => {
-                "Failed Creating Label! Label with same name already exists under organization"
+            ConsoleError::UnknownLabel(label) => write!(f, "No label named \"{}\"", label),
+            ConsoleError::NoMatchingDevice(selector) => write!(f, "No device with {}", selector),
+            ConsoleError::AmbiguousDevice { selector, devices } => write!(
+                f,
+                "{} devices have {}: {}. Select the device by UUID instead",
+                devices.len(),
+                selector,
+                devices.join(", ")
+            ),
+            ConsoleError::AmbiguousLabel { label, labels } => write!(
+                f,
+                "\"{}\" matches {} labels: {}. Give the label's UUID instead",
+                label,
+                labels.len(),
+                labels.join(", ")
+            ),
+            ConsoleError::InvalidUuid(input) => write!(
+                f,
+                "Invalid UUID input. Expected in hyphenated form \"00000000-0000-0000-0000-000000000000\" [input: {}]",
+                input
+            ),
+            ConsoleError::NotFound(error) => write!(f, "Not found. {}", error),
+            ConsoleError::Conflict(error) => write!(
+                f,
+                "Conflict. A record with identical credentials or name already exists. {}",
+                error
+            ),
+            ConsoleError::Unauthorized(error) => write!(
+                f,
+                "Failed to connect to Helium API server. Unauthorized. {}",
+                error
+            ),
+            ConsoleError::RateLimited { error, .. } => write!(f, "Rate limited. {}", error),
+            ConsoleError::ServerError(error) => write!(f, "Server error. {}", error),
+            ConsoleError::UnexpectedStatus(error) => write!(
+                f,
+                "Failed to connect to Helium API server. Unexpected response. {}",
+                error
+            ),
+            ConsoleError::Decode { path, source, .. } => {
+                write!(f, "Failed to decode response from {}: {}", path, source)
             }
-            Error::NewLabelApi => {
-                "Failed Creating Label! Unknown server error"
+            ConsoleError::Encode { path, source } => {
+                write!(f, "Failed to encode request for {}: {}", path, source)
             }
-            Error::NewDeviceLabelApi => {
-                "Failed Creating Device Label! Unknown server error"
+            ConsoleError::Transport { path, source } => {
+                write!(f, "Failed to connect to Helium API server ({}): {}", path, source)
             }
-            Error::UnauthorizedApi => {
-                "Failed to connect to Helium API server. Unauthorized"
-            }
-            Error::HttpErrorApi => {
-                "Failed to connect to Helium API server. Unexpected response"
-            }
-        };
-        write!(f, "{}", msg)
+        }
     }
 }
 
-impl ::std::error::Error for Error {
-    fn description(&self) -> &str {
+impl ::std::error::Error for ConsoleError {
+    fn source(&self) -> Option<&(dyn ::std::error::Error + 'static)> {
         match self {
-            Error::InvalidAppEui => "Invalid AppEui input. Must be 8 bytes represented in hex (\"0123456789ABCDEF\")",
-            Error::InvalidAppKey => "Invalid AppKey input. Must be 16 bytes represented in hex (\"0123456789ABCDEF0123456789ABCDEF\")",
-            Error::InvalidDevEui => "Invalid DevEui input. Must be 8 bytes represented in hex (\"0123456789ABCDEF\")",
-            Error::InvalidApiKey => "Invalid Api Key. Must be 32 bytes represented in base64",
-            Error::InvalidUuid => "Invalid UUID input. Expected in hyphenated form \"00000000-0000-0000-0000-000000000000\"",
-            Error::NewDevice422 => "Failed Creating Device! Device with identical credentials already exists",
-            Error::NewDeviceApi => "Failed Creating Device! Unknown server error",
-            Error::NewLabel422 => "Failed Creating Label! Label with same name already exists under organization",
-            Error::NewLabelApi => "Failed Creating Label! Unknown server error",
-            Error::NewDeviceLabelApi => "Failed Creating Device Label! Unknown server error",
-            Error::UnauthorizedApi => "Failed to connect to Helium API server. Unauthorized",
-            Error::HttpErrorApi => "Failed to connect to Helium API server. Unexpected response",
+            ConsoleError::Decode { source, .. } => Some(source),
+            ConsoleError::Encode { source, .. } => Some(source),
+            ConsoleError::Transport { source, .. } => Some(source),
+            _ => None,
         }
     }
 }
@@ -1,20 +1,51 @@
-use rand::Rng;
 use serde_derive::{Deserialize, Serialize};
 
+pub mod api;
+pub use api::ConsoleApi;
+pub mod chirpstack;
 pub mod client;
+pub mod device_index;
+pub use device_index::DeviceIndex;
+pub mod dry_run;
+pub use dry_run::DryRun;
 pub mod errors;
 pub use errors::*;
+pub mod lorawan;
+pub use lorawan::{AppEui, AppKey, DevEui};
+pub mod retry;
+pub mod secret;
+pub use secret::Secret;
+pub mod source_api;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod ttn;
+pub mod ttn_v3;
 
 pub use oauth2;
 
-pub type Result<T = ()> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub type Result<T = ()> = std::result::Result<T, ConsoleError>;
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct Organization {
+    id: String,
+    name: String,
+}
+
+impl Organization {
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+}
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Device {
-    app_eui: String,
-    app_key: String,
-    dev_eui: String,
+    app_eui: AppEui,
+    app_key: Secret<AppKey>,
+    dev_eui: DevEui,
     id: String,
     name: String,
     organization_id: String,
@@ -26,130 +57,256 @@ impl Device {
         &self.id
     }
 
-    pub fn app_eui(&self) -> &String {
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn app_eui(&self) -> &AppEui {
         &self.app_eui
     }
 
-    pub fn dev_eui(&self) -> &String {
+    pub fn dev_eui(&self) -> &DevEui {
         &self.dev_eui
     }
 
-    pub fn app_key(&self) -> &String {
+    pub fn app_key(&self) -> &Secret<AppKey> {
         &self.app_key
     }
 
-    pub fn random_dev_eui() -> String {
-        let mut rng = rand::thread_rng();
-        let mut deveui_bytes = Vec::new();
-        for _ in 0..8 {
-            deveui_bytes.push(rng.gen())
-        }
-        hex::encode(deveui_bytes)
+    pub fn random_dev_eui() -> DevEui {
+        DevEui::random()
     }
 
-    pub fn random_app_key() -> String {
-        let mut rng = rand::thread_rng();
-        let mut app_key_bytes = Vec::new();
-        for _ in 0..16 {
-            app_key_bytes.push(rng.gen())
-        }
-        hex::encode(app_key_bytes)
+    pub fn random_app_key() -> AppKey {
+        AppKey::random()
     }
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct GetDevice {
-    app_eui: String,
-    app_key: String,
-    dev_eui: String,
+    app_eui: AppEui,
+    app_key: Secret<AppKey>,
+    dev_eui: DevEui,
 }
 
 impl GetDevice {
-    pub fn from_user_input(app_eui: String, app_key: String, dev_eui: String) -> Result<GetDevice> {
-        let app_eui_decoded = hex::decode(app_eui.clone())?;
-        if app_eui_decoded.len() != 8 {
-            return Err(Error::InvalidAppEui.into());
-        }
-
-        let app_key_decoded = hex::decode(app_key.clone())?;
-        if app_key_decoded.len() != 16 {
-            return Err(Error::InvalidAppKey.into());
-        }
-
-        let dev_eui_decoded = hex::decode(dev_eui.clone())?;
-        if dev_eui_decoded.len() != 8 {
-            return Err(Error::InvalidDevEui.into());
-        }
-
-        Ok(GetDevice {
+    pub fn new(app_eui: AppEui, app_key: AppKey, dev_eui: DevEui) -> GetDevice {
+        GetDevice {
             app_eui,
-            app_key,
+            app_key: Secret::new(app_key),
             dev_eui,
-        })
+        }
+    }
+
+    pub fn from_user_input(app_eui: String, app_key: String, dev_eui: String) -> Result<GetDevice> {
+        Ok(GetDevice::new(
+            app_eui.parse()?,
+            app_key.parse()?,
+            dev_eui.parse()?,
+        ))
     }
 
-    pub fn app_eui(&self) -> &String {
+    pub fn app_eui(&self) -> &AppEui {
         &self.app_eui
     }
 
-    pub fn dev_eui(&self) -> &String {
+    pub fn dev_eui(&self) -> &DevEui {
         &self.dev_eui
     }
 
-    pub fn app_key(&self) -> &String {
+    pub fn app_key(&self) -> &Secret<AppKey> {
         &self.app_key
     }
 }
 
+impl From<&NewDevice> for GetDevice {
+    fn from(new_device: &NewDevice) -> GetDevice {
+        GetDevice::new(
+            new_device.app_eui,
+            *new_device.app_key.expose_secret(),
+            new_device.dev_eui,
+        )
+    }
+}
+
+/// Picks devices out of the organization's device list, so a device can be
+/// found without sending its AppKey to the server
+#[derive(Clone, Debug)]
+pub enum DeviceSelector {
+    /// The app_eui, app_key and dev_eui a device was created with
+    Credentials(GetDevice),
+    DevEui(DevEui),
+    Name(String),
+    /// Devices carrying this label
+    Label(Label),
+}
+
+impl DeviceSelector {
+    pub fn matches(&self, device: &DetailedDevice) -> bool {
+        match self {
+            DeviceSelector::Credentials(get_device) => {
+                device.dev_eui == get_device.dev_eui
+                    && device.app_eui == get_device.app_eui
+                    && device.app_key == get_device.app_key
+            }
+            DeviceSelector::DevEui(dev_eui) => device.dev_eui == *dev_eui,
+            DeviceSelector::Name(name) => device.name == *name,
+            DeviceSelector::Label(label) => device.labels.contains(&label.id),
+        }
+    }
+
+    /// The one device out of `devices` that matches, or an error naming
+    /// every candidate when the selector is ambiguous
+    pub fn pick(
+        &self,
+        devices: impl IntoIterator<Item = DetailedDevice>,
+    ) -> Result<DetailedDevice> {
+        let mut found: Vec<DetailedDevice> = devices
+            .into_iter()
+            .filter(|device| self.matches(device))
+            .collect();
+        match found.len() {
+            0 => Err(ConsoleError::NoMatchingDevice(self.to_string())),
+            1 => Ok(found.remove(0)),
+            _ => Err(ConsoleError::AmbiguousDevice {
+                selector: self.to_string(),
+                devices: found
+                    .iter()
+                    .map(|device| format!("\"{}\" ({})", device.name, device.id))
+                    .collect(),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeviceSelector::Credentials(get_device) => write!(
+                f,
+                "dev_eui {} and app_eui {}",
+                get_device.dev_eui, get_device.app_eui
+            ),
+            DeviceSelector::DevEui(dev_eui) => write!(f, "dev_eui {}", dev_eui),
+            DeviceSelector::Name(name) => write!(f, "name \"{}\"", name),
+            DeviceSelector::Label(label) => write!(f, "label \"{}\"", label.name),
+        }
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct NewDevice {
-    app_eui: String,
-    app_key: String,
-    dev_eui: String,
+    app_eui: AppEui,
+    app_key: Secret<AppKey>,
+    dev_eui: DevEui,
     name: String,
 }
 
 impl NewDevice {
+    pub fn new(app_eui: AppEui, app_key: AppKey, dev_eui: DevEui, name: String) -> NewDevice {
+        NewDevice {
+            app_eui,
+            app_key: Secret::new(app_key),
+            dev_eui,
+            name,
+        }
+    }
+
     pub fn from_user_input(
         app_eui: String,
         app_key: String,
         dev_eui: String,
         name: String,
     ) -> Result<NewDevice> {
-        let app_eui_decoded = hex::decode(app_eui.clone())?;
-        if app_eui_decoded.len() != 8 {
-            return Err(Error::InvalidAppEui.into());
-        }
-
-        let app_key_decoded = hex::decode(app_key.clone())?;
-        if app_key_decoded.len() != 16 {
-            return Err(Error::InvalidAppKey.into());
-        }
-
-        let dev_eui_decoded = hex::decode(dev_eui.clone())?;
-        if dev_eui_decoded.len() != 8 {
-            return Err(Error::InvalidDevEui.into());
-        }
-
-        Ok(NewDevice {
-            app_eui,
-            app_key,
-            dev_eui,
+        Ok(NewDevice::new(
+            app_eui.parse()?,
+            app_key.parse()?,
+            dev_eui.parse()?,
             name,
-        })
+        ))
     }
 
-    pub fn app_eui(&self) -> &String {
+    pub fn app_eui(&self) -> &AppEui {
         &self.app_eui
     }
 
-    pub fn app_key(&self) -> &String {
+    pub fn app_key(&self) -> &Secret<AppKey> {
         &self.app_key
     }
 
-    pub fn dev_eui(&self) -> &String {
+    pub fn dev_eui(&self) -> &DevEui {
         &self.dev_eui
     }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+}
+
+/// Partial update of a device record. Only the fields that were set
+/// are sent, everything else is left untouched on the server.
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+pub struct DeviceUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    adr_allowed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cf_list_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config_profile_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rx_delay: Option<u8>,
+}
+
+impl DeviceUpdate {
+    pub fn new() -> DeviceUpdate {
+        DeviceUpdate::default()
+    }
+
+    pub fn name(mut self, name: String) -> DeviceUpdate {
+        self.name = Some(name);
+        self
+    }
+
+    /// Inactive devices are paused: their packets are not routed
+    pub fn active(mut self, active: bool) -> DeviceUpdate {
+        self.active = Some(active);
+        self
+    }
+
+    pub fn adr_allowed(mut self, adr_allowed: bool) -> DeviceUpdate {
+        self.adr_allowed = Some(adr_allowed);
+        self
+    }
+
+    pub fn cf_list_enabled(mut self, cf_list_enabled: bool) -> DeviceUpdate {
+        self.cf_list_enabled = Some(cf_list_enabled);
+        self
+    }
+
+    pub fn config_profile_id(mut self, config_profile_id: String) -> Result<DeviceUpdate> {
+        validate_uuid_input(&config_profile_id)?;
+        self.config_profile_id = Some(config_profile_id);
+        Ok(self)
+    }
+
+    pub fn rx_delay(mut self, rx_delay: u8) -> DeviceUpdate {
+        self.rx_delay = Some(rx_delay);
+        self
+    }
+
+    /// True when no field has been set
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.active.is_none()
+            && self.adr_allowed.is_none()
+            && self.cf_list_enabled.is_none()
+            && self.config_profile_id.is_none()
+            && self.rx_delay.is_none()
+    }
 }
 
 impl NewLabel {
@@ -180,6 +337,80 @@ impl Label {
     }
 }
 
+/// How label names given by users are matched against existing labels
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelMatch {
+    /// Names must be identical
+    Exact,
+    /// Names match regardless of case; new labels keep the case given
+    #[default]
+    CaseInsensitive,
+    /// Names are uppercased before lookup and creation, as older
+    /// versions of the CLI did
+    NormalizeUpper,
+}
+
+impl LabelMatch {
+    /// The name a new label is created under
+    pub fn normalize(&self, name: &str) -> String {
+        match self {
+            LabelMatch::NormalizeUpper => name.to_uppercase(),
+            LabelMatch::Exact | LabelMatch::CaseInsensitive => name.to_string(),
+        }
+    }
+
+    /// Whether the requested name refers to the label named `name`
+    pub fn matches(&self, requested: &str, name: &str) -> bool {
+        match self {
+            LabelMatch::Exact => requested == name,
+            LabelMatch::CaseInsensitive => requested.to_lowercase() == name.to_lowercase(),
+            LabelMatch::NormalizeUpper => requested.to_uppercase() == name,
+        }
+    }
+
+    /// Every label `requested` refers to, by UUID or by name. An identical
+    /// name comes first, so it wins over other case variants; the rest are
+    /// ordered by name, then id.
+    pub fn select(&self, requested: &str, labels: impl IntoIterator<Item = Label>) -> Vec<Label> {
+        let by_id = validate_uuid_input(requested).is_ok();
+        let mut found: Vec<Label> = labels
+            .into_iter()
+            .filter(|label| {
+                if by_id {
+                    label.id == requested
+                } else {
+                    self.matches(requested, &label.name)
+                }
+            })
+            .collect();
+        found.sort_by(|a, b| {
+            (a.name != requested, &a.name, &a.id).cmp(&(b.name != requested, &b.name, &b.id))
+        });
+        found
+    }
+
+    /// The one label `requested` refers to, if any. Several case variants
+    /// without an identical name among them are an `AmbiguousLabel` error.
+    pub fn select_one(
+        &self,
+        requested: &str,
+        labels: impl IntoIterator<Item = Label>,
+    ) -> Result<Option<Label>> {
+        let found = self.select(requested, labels);
+        match found.as_slice() {
+            [first, _, ..] if first.name != requested => Err(ConsoleError::AmbiguousLabel {
+                label: requested.to_string(),
+                labels: found
+                    .iter()
+                    .map(|label| format!("\"{}\" ({})", label.name, label.id))
+                    .collect(),
+            }),
+            _ => Ok(found.into_iter().next()),
+        }
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct DeviceLabel {
     label: String,
@@ -198,18 +429,17 @@ impl DeviceLabel {
 
 /// Throws an error if UUID isn't properly input
 pub fn validate_uuid_input(id: &str) -> Result {
-    if let Err(err) = uuid::Uuid::parse_str(id) {
-        println!("{} [input: {}]", err, id);
-        return Err(Error::InvalidUuid.into());
+    match uuid::Uuid::parse_str(id) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ConsoleError::InvalidUuid(id.to_string())),
     }
-    Ok(())
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct DetailedDevice {
-    app_eui: String,
-    app_key: String,
-    dev_eui: String,
+    app_eui: AppEui,
+    app_key: Secret<AppKey>,
+    dev_eui: DevEui,
     id: String,
     name: String,
     organization_id: String,
@@ -227,3 +457,65 @@ pub struct DetailedDevice {
     rx_delay: u8,
     total_packets: usize,
 }
+
+impl DetailedDevice {
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn app_eui(&self) -> &AppEui {
+        &self.app_eui
+    }
+
+    pub fn dev_eui(&self) -> &DevEui {
+        &self.dev_eui
+    }
+
+    pub fn app_key(&self) -> &Secret<AppKey> {
+        &self.app_key
+    }
+
+    /// UUIDs of the labels attached to the device
+    pub fn labels(&self) -> &Vec<String> {
+        &self.labels
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    pub fn in_xor_filter(&self) -> bool {
+        self.in_xor_filter
+    }
+
+    pub fn dc_usage(&self) -> usize {
+        self.dc_usage
+    }
+
+    pub fn total_packets(&self) -> usize {
+        self.total_packets
+    }
+
+    /// Timestamp of the last uplink as sent by the server, if there was one
+    pub fn last_connected(&self) -> Option<&str> {
+        self.last_connected.as_str()
+    }
+}
+
+impl From<&DetailedDevice> for Device {
+    fn from(device: &DetailedDevice) -> Device {
+        Device {
+            app_eui: device.app_eui,
+            app_key: device.app_key,
+            dev_eui: device.dev_eui,
+            id: device.id.clone(),
+            name: device.name.clone(),
+            organization_id: device.organization_id.clone(),
+            oui: device.oui,
+        }
+    }
+}
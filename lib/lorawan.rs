@@ -0,0 +1,176 @@
+use super::ConsoleError;
+use rand::Rng;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// Defines a fixed-size identifier that is written as hex.
+///
+/// Parsing accepts either case and ignores ':', '-' and ' ' separators, so
+/// "70B3D57ED0001234", "70:b3:d5:7e:d0:00:12:34" and "70 B3 D5 7E D0 00 12 34"
+/// are the same value. Display and serialization use plain uppercase hex.
+macro_rules! hex_identifier {
+    ($(#[$doc:meta])* $name:ident, $len:expr, $error:expr) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name([u8; $len]);
+
+        impl $name {
+            pub fn from_bytes(bytes: [u8; $len]) -> $name {
+                $name(bytes)
+            }
+
+            pub fn as_bytes(&self) -> &[u8; $len] {
+                &self.0
+            }
+
+            pub fn random() -> $name {
+                let mut bytes = [0; $len];
+                rand::thread_rng().fill(&mut bytes);
+                $name(bytes)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ConsoleError;
+
+            fn from_str(input: &str) -> Result<$name, ConsoleError> {
+                let digits: String = input
+                    .trim()
+                    .chars()
+                    .filter(|c| !matches!(c, ':' | '-' | ' '))
+                    .collect();
+                match hex::decode(&digits) {
+                    Ok(decoded) if decoded.len() == $len => {
+                        let mut bytes = [0; $len];
+                        bytes.copy_from_slice(&decoded);
+                        Ok($name(bytes))
+                    }
+                    _ => Err($error),
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", hex::encode_upper(self.0))
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<$name, D::Error> {
+                let input = String::deserialize(deserializer)?;
+                input.parse().map_err(de::Error::custom)
+            }
+        }
+    };
+}
+
+hex_identifier!(
+    /// 64-bit end-device identifier
+    DevEui,
+    8,
+    ConsoleError::InvalidDevEui
+);
+
+hex_identifier!(
+    /// 64-bit application (join server) identifier
+    AppEui,
+    8,
+    ConsoleError::InvalidAppEui
+);
+
+hex_identifier!(
+    /// 128-bit OTAA root key
+    AppKey,
+    16,
+    ConsoleError::InvalidAppKey
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEV_EUI: [u8; 8] = [0x70, 0xB3, 0xD5, 0x7E, 0xD0, 0x00, 0x12, 0x34];
+
+    #[test]
+    fn parses_every_separator_and_case() {
+        for input in &[
+            "70B3D57ED0001234",
+            "70b3d57ed0001234",
+            "70:b3:d5:7e:d0:00:12:34",
+            "70-B3-D5-7E-D0-00-12-34",
+            "70 B3 D5 7E D0 00 12 34",
+            "70:B3-d5 7E:d0-00 12:34",
+            "  70B3D57ED0001234\n",
+        ] {
+            let parsed: DevEui = input.parse().unwrap_or_else(|_| panic!("{:?}", input));
+            assert_eq!(parsed, DevEui::from_bytes(DEV_EUI), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn displays_and_serializes_plain_uppercase() {
+        let dev_eui: DevEui = "70:b3:d5:7e:d0:00:12:34".parse().unwrap();
+        assert_eq!(dev_eui.to_string(), "70B3D57ED0001234");
+        assert_eq!(
+            serde_json::to_string(&dev_eui).unwrap(),
+            "\"70B3D57ED0001234\""
+        );
+        let round_trip: DevEui = serde_json::from_str("\"70-b3-d5-7e-d0-00-12-34\"").unwrap();
+        assert_eq!(round_trip, dev_eui);
+    }
+
+    #[test]
+    fn rejects_wrong_lengths() {
+        for input in &[
+            "",
+            "70B3D57ED00012",
+            "70B3D57ED000123456",
+            "70B3D57ED000123",
+        ] {
+            assert!(
+                matches!(input.parse::<DevEui>(), Err(ConsoleError::InvalidDevEui)),
+                "{:?}",
+                input
+            );
+        }
+        // an AppEui is 8 bytes and an AppKey 16
+        assert!(matches!(
+            "70B3D57ED00012347".parse::<AppEui>(),
+            Err(ConsoleError::InvalidAppEui)
+        ));
+        assert!(matches!(
+            "70B3D57ED0001234".parse::<AppKey>(),
+            Err(ConsoleError::InvalidAppKey)
+        ));
+        assert!("000102030405060708090A0B0C0D0E0F".parse::<AppKey>().is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_hex() {
+        for input in &[
+            "70B3D57ED000123G",
+            "70B3D57ED00012_4",
+            "0x70B3D57ED00012",
+            "70.B3.D5.7E.D0.00.12.34",
+        ] {
+            assert!(
+                matches!(input.parse::<DevEui>(), Err(ConsoleError::InvalidDevEui)),
+                "{:?}",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use super::ConsoleError;
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Controls how `client::Client` retries failed requests
+///
+/// Only idempotent requests (GET, DELETE) are retried automatically.
+/// Device creation is retried only when `retry_device_creation` is set.
+/// A retried POST that comes back 422 (Conflict) then resolves to the
+/// existing device, since an earlier attempt landed despite its error.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled on every further attempt
+    pub base_delay_ms: u64,
+    /// Upper bound on a single delay, including one asked for with Retry-After
+    pub max_delay_ms: u64,
+    /// Fraction of each delay (0.0 to 1.0) that is randomized
+    pub jitter: f64,
+    /// HTTP statuses worth retrying
+    pub retryable_statuses: Vec<u16>,
+    /// Opt-in retries for `post_device`
+    pub retry_device_creation: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+            jitter: 0.2,
+            retryable_statuses: vec![429, 500, 502, 503, 504],
+            retry_device_creation: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes exactly one attempt
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Whether `attempt` (starting at 1) failing with `error` should be retried
+    pub fn should_retry(&self, attempt: u32, error: &ConsoleError) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+        match error {
+            ConsoleError::Transport { source, .. } => source.is_timeout() || source.is_connect(),
+            _ => match error.status() {
+                Some(status) => self.retryable_statuses.contains(&status.as_u16()),
+                None => false,
+            },
+        }
+    }
+
+    /// How long to wait after `attempt` (starting at 1) failed with `error`
+    pub fn delay(&self, attempt: u32, error: &ConsoleError) -> Duration {
+        if let ConsoleError::RateLimited {
+            retry_after: Some(retry_after),
+            ..
+        } = error
+        {
+            return (*retry_after).min(Duration::from_millis(self.max_delay_ms));
+        }
+
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay_ms
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay_ms);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let delay = if jitter > 0.0 {
+            let reduction = rand::thread_rng().gen_range(0.0, jitter);
+            (delay as f64 * (1.0 - reduction)) as u64
+        } else {
+            delay
+        };
+        Duration::from_millis(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ApiError;
+    use reqwest::{Method, StatusCode};
+
+    fn rate_limited(seconds: u64) -> ConsoleError {
+        let error = ApiError {
+            method: Method::GET,
+            path: "api/v1/devices".to_string(),
+            status: StatusCode::TOO_MANY_REQUESTS,
+            body: String::new(),
+        };
+        ConsoleError::from_response(error, Some(Duration::from_secs(seconds)))
+    }
+
+    #[test]
+    fn caps_retry_after_at_the_maximum_delay() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(1, &rate_limited(2)), Duration::from_secs(2));
+        assert_eq!(
+            policy.delay(1, &rate_limited(u64::MAX)),
+            Duration::from_millis(policy.max_delay_ms)
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::{cell::Cell, fmt};
+
+pub(crate) const MASK: &str = "********";
+
+/// Fields that hold key material when records are serialized
+pub const SECRET_FIELDS: &[&str] = &["app_key", "app_s_key", "nwk_s_key"];
+
+thread_local! {
+    static REVEALED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` with secrets revealed on the current thread.
+///
+/// Outside of this scope a `Secret` is masked when it is printed with
+/// Debug or Display. Serialization always writes the real value, so
+/// records round-trip; use `mask` on serialized records before showing
+/// them.
+pub fn reveal<R>(f: impl FnOnce() -> R) -> R {
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            REVEALED.with(|revealed| revealed.set(self.0));
+        }
+    }
+
+    let _restore = Restore(REVEALED.with(|revealed| revealed.replace(true)));
+    f()
+}
+
+pub(crate) fn is_revealed() -> bool {
+    REVEALED.with(|revealed| revealed.get())
+}
+
+/// Key material that must not end up in logs by accident
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Secret<T> {
+        Secret(value)
+    }
+
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Secret<T> {
+        Secret(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if is_revealed() {
+            self.0.fmt(f)
+        } else {
+            f.write_str(MASK)
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if is_revealed() {
+            self.0.fmt(f)
+        } else {
+            f.write_str(MASK)
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Secret<T>, D::Error> {
+        T::deserialize(deserializer).map(Secret)
+    }
+}
+
+/// Replaces every `SECRET_FIELDS` value in a serialized record, at any
+/// depth, with a mask
+pub fn mask(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&name.as_str()) && !field.is_null() {
+                    *field = Value::String(MASK.to_string());
+                } else {
+                    mask(field);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(mask),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppEui, AppKey, DevEui, NewDevice};
+
+    fn device() -> NewDevice {
+        NewDevice::new(
+            AppEui::from_bytes([1; 8]),
+            AppKey::from_bytes([2; 16]),
+            DevEui::from_bytes([3; 8]),
+            "sensor".to_string(),
+        )
+    }
+
+    #[test]
+    fn serializes_keys_so_records_round_trip() {
+        let json = serde_json::to_string(&device()).unwrap();
+        assert!(json.contains(&"02".repeat(16)));
+        let decoded: NewDevice = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.app_key(), device().app_key());
+    }
+
+    #[test]
+    fn masks_debug_and_display_outside_reveal() {
+        let key = Secret::new("key material".to_string());
+        assert_eq!(format!("{} {:?}", key, key), format!("{} {}", MASK, MASK));
+        assert_eq!(
+            reveal(|| format!("{} {:?}", key, key)),
+            "key material \"key material\""
+        );
+        assert!(!format!("{:?}", device()).contains(&"02".repeat(16)));
+    }
+
+    #[test]
+    fn masks_secret_fields_at_any_depth() {
+        let mut value = serde_json::json!({
+            "devices": [serde_json::to_value(device()).unwrap()],
+            "app_s_key": null,
+            "name": "kept",
+        });
+        mask(&mut value);
+        assert_eq!(value["devices"][0]["app_key"], MASK);
+        assert_eq!(value["devices"][0]["name"], "sensor");
+        assert!(value["app_s_key"].is_null());
+        assert_eq!(value["name"], "kept");
+    }
+}
@@ -0,0 +1,130 @@
+//! HTTP plumbing shared by the clients of other network servers, which
+//! are read through JSON APIs with a bearer token.
+
+use super::Secret;
+use reqwest::{Client as ReqwestClient, StatusCode};
+use serde::de::DeserializeOwned;
+use std::{error::Error as stdError, fmt, time::Duration};
+
+const DEFAULT_TIMEOUT: u64 = 120;
+
+// records per request when listing
+const PAGE_SIZE: usize = 100;
+
+pub type Result<T = ()> = std::result::Result<T, Box<dyn stdError>>;
+
+/// How a server pages its listings
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Paging {
+    /// `limit` and a 1-based `page`
+    Page,
+    /// `limit` and `offset`
+    Offset,
+}
+
+pub(crate) struct SourceClient {
+    client: ReqwestClient,
+    base_url: String,
+    // header carrying "Bearer <token>"
+    auth_header: &'static str,
+    token: Secret<String>,
+}
+
+impl SourceClient {
+    pub fn new(base_url: &str, auth_header: &'static str, token: &str) -> Result<SourceClient> {
+        Ok(SourceClient {
+            client: ReqwestClient::builder()
+                .timeout(Duration::from_secs(DEFAULT_TIMEOUT))
+                .build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            auth_header,
+            token: Secret::new(token.to_string()),
+        })
+    }
+
+    /// `path` is relative to the base URL; error statuses are a `StatusError`
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self
+            .client
+            .get(format!("{}/{}", self.base_url, path).as_str())
+            .header(
+                self.auth_header,
+                format!("Bearer {}", self.token.expose_secret()),
+            )
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if status.is_success() {
+            Ok(serde_json::from_str(&body)?)
+        } else {
+            Err(StatusError {
+                path: path.to_string(),
+                status,
+                body,
+            }
+            .into())
+        }
+    }
+
+    /// Every page of a listing. `records` takes the records out of a page;
+    /// a page shorter than the page size is the last one.
+    pub async fn get_all<P: DeserializeOwned, T>(
+        &self,
+        path: &str,
+        paging: Paging,
+        records: fn(P) -> Vec<T>,
+    ) -> Result<Vec<T>> {
+        let separator = if path.contains('?') { '&' } else { '?' };
+        let mut all = Vec::new();
+        for page in 1.. {
+            let position = match paging {
+                Paging::Page => format!("page={}", page),
+                Paging::Offset => format!("offset={}", all.len()),
+            };
+            let batch = records(
+                self.get(&format!(
+                    "{}{}limit={}&{}",
+                    path, separator, PAGE_SIZE, position
+                ))
+                .await?,
+            );
+            let done = batch.len() < PAGE_SIZE;
+            all.extend(batch);
+            if done {
+                break;
+            }
+        }
+        Ok(all)
+    }
+}
+
+/// A request the server answered with an error status
+#[derive(Debug)]
+pub struct StatusError {
+    pub path: String,
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl StatusError {
+    /// Whether `err` is a 404, which lookups of optional records ignore
+    pub fn is_not_found(err: &(dyn stdError + 'static)) -> bool {
+        match err.downcast_ref::<StatusError>() {
+            Some(err) => err.status == StatusCode::NOT_FOUND,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "GET {} returned {}: {}",
+            self.path, self.status, self.body
+        )
+    }
+}
+
+impl stdError for StatusError {}
@@ -0,0 +1,424 @@
+//! In-memory stand-in for the Console API, for testing code written
+//! against `ConsoleApi` without a network.
+
+use super::*;
+use async_trait::async_trait;
+use reqwest::{Method, StatusCode};
+use std::sync::{Mutex, MutexGuard};
+
+/// Keeps devices and labels in memory and enforces the same rules as the
+/// real server: 422 on duplicate device credentials or label names and
+/// 404 on unknown devices, labels and device labels.
+#[derive(Debug)]
+pub struct InMemoryConsole {
+    organization_id: String,
+    label_match: LabelMatch,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    devices: Vec<DetailedDevice>,
+    labels: Vec<Label>,
+}
+
+fn new_id() -> String {
+    uuid::Uuid::new_v4().to_hyphenated().to_string()
+}
+
+fn error(method: Method, path: String, status: StatusCode, body: &str) -> ConsoleError {
+    ConsoleError::from_response(
+        ApiError {
+            method,
+            path,
+            status,
+            body: body.to_string(),
+        },
+        None,
+    )
+}
+
+impl Default for InMemoryConsole {
+    fn default() -> InMemoryConsole {
+        InMemoryConsole::new()
+    }
+}
+
+impl InMemoryConsole {
+    pub fn new() -> InMemoryConsole {
+        InMemoryConsole {
+            organization_id: new_id(),
+            label_match: LabelMatch::default(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Matches label names under `label_match` instead of the default
+    pub fn with_label_match(mut self, label_match: LabelMatch) -> InMemoryConsole {
+        self.label_match = label_match;
+        self
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("in-memory console state poisoned")
+    }
+
+    /// Snapshot of every device currently stored
+    pub fn devices(&self) -> Vec<DetailedDevice> {
+        self.state().devices.clone()
+    }
+
+    /// Snapshot of every label currently stored
+    pub fn labels(&self) -> Vec<Label> {
+        self.state().labels.clone()
+    }
+}
+
+impl State {
+    fn device_mut(&mut self, method: Method, id: &str) -> Result<&mut DetailedDevice> {
+        self.devices
+            .iter_mut()
+            .find(|device| device.id == id)
+            .ok_or_else(|| {
+                error(
+                    method,
+                    format!("api/v1/devices/{}", id),
+                    StatusCode::NOT_FOUND,
+                    "Device not found",
+                )
+            })
+    }
+
+    fn has_label(&self, id: &str) -> bool {
+        self.labels.iter().any(|label| label.id == id)
+    }
+}
+
+#[async_trait]
+impl ConsoleApi for InMemoryConsole {
+    async fn get_organization(&self) -> Result<Organization> {
+        Ok(Organization {
+            id: self.organization_id.clone(),
+            name: "In-memory organization".to_string(),
+        })
+    }
+
+    async fn get_devices(&self) -> Result<Vec<Device>> {
+        Ok(self.state().devices.iter().map(Device::from).collect())
+    }
+
+    async fn get_detailed_devices(&self) -> Result<Vec<DetailedDevice>> {
+        Ok(self.devices())
+    }
+
+    async fn get_device(&self, get_device: &GetDevice) -> Result<Device> {
+        let device = self
+            .find_device(&DeviceSelector::Credentials(get_device.clone()))
+            .await?;
+        Ok(Device::from(&device))
+    }
+
+    async fn find_device(&self, selector: &DeviceSelector) -> Result<DetailedDevice> {
+        selector.pick(self.devices())
+    }
+
+    async fn get_device_by_id(&self, id: &str) -> Result<Device> {
+        let mut state = self.state();
+        let device = state.device_mut(Method::GET, id)?;
+        Ok(Device::from(&*device))
+    }
+
+    async fn post_device(&self, new_device_request: &NewDevice) -> Result<Device> {
+        let mut state = self.state();
+        let duplicate = state.devices.iter().any(|device| {
+            device.dev_eui == new_device_request.dev_eui
+                && device.app_eui == new_device_request.app_eui
+                && device.app_key == new_device_request.app_key
+        });
+        if duplicate {
+            return Err(error(
+                Method::POST,
+                "api/v1/devices".to_string(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "A device with the same dev_eui, app_eui, and app_key already exists",
+            ));
+        }
+
+        let device = DetailedDevice {
+            app_eui: new_device_request.app_eui,
+            app_key: new_device_request.app_key,
+            dev_eui: new_device_request.dev_eui,
+            id: new_id(),
+            name: new_device_request.name.clone(),
+            organization_id: self.organization_id.clone(),
+            oui: 1,
+            active: true,
+            adr_allowed: serde_json::Value::Bool(false),
+            cf_list_enabled: serde_json::Value::Bool(false),
+            config_profile_id: serde_json::Value::Null,
+            dc_usage: 0,
+            in_xor_filter: false,
+            labels: Vec::new(),
+            last_connected: serde_json::Value::Null,
+            rx_delay: 1,
+            total_packets: 0,
+        };
+        let created = Device::from(&device);
+        state.devices.push(device);
+        Ok(created)
+    }
+
+    async fn update_device(&self, id: &str, update: &DeviceUpdate) -> Result {
+        let mut state = self.state();
+        let device = state.device_mut(Method::PUT, id)?;
+        if let Some(name) = &update.name {
+            device.name = name.clone();
+        }
+        if let Some(active) = update.active {
+            device.active = active;
+        }
+        if let Some(adr_allowed) = update.adr_allowed {
+            device.adr_allowed = serde_json::Value::Bool(adr_allowed);
+        }
+        if let Some(cf_list_enabled) = update.cf_list_enabled {
+            device.cf_list_enabled = serde_json::Value::Bool(cf_list_enabled);
+        }
+        if let Some(config_profile_id) = &update.config_profile_id {
+            device.config_profile_id = serde_json::Value::String(config_profile_id.clone());
+        }
+        if let Some(rx_delay) = update.rx_delay {
+            device.rx_delay = rx_delay;
+        }
+        Ok(())
+    }
+
+    async fn delete_device(&self, id: &str) -> Result {
+        let mut state = self.state();
+        state.device_mut(Method::DELETE, id)?;
+        state.devices.retain(|device| device.id != id);
+        Ok(())
+    }
+
+    async fn get_labels(&mut self) -> Result<Vec<Label>> {
+        Ok(self.labels())
+    }
+
+    async fn post_label(&self, new_label_request: &NewLabel) -> Result<Label> {
+        let mut state = self.state();
+        if state
+            .labels
+            .iter()
+            .any(|label| label.name == new_label_request.name)
+        {
+            return Err(error(
+                Method::POST,
+                "api/v1/labels".to_string(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "This label name has already been used",
+            ));
+        }
+        let label = Label {
+            id: new_id(),
+            name: new_label_request.name.clone(),
+        };
+        state.labels.push(label.clone());
+        Ok(label)
+    }
+
+    async fn rename_label(&self, id: &str, name: &str) -> Result {
+        let path = format!("api/v1/labels/{}", id);
+        let mut state = self.state();
+        if state
+            .labels
+            .iter()
+            .any(|label| label.name == name && label.id != id)
+        {
+            return Err(error(
+                Method::PUT,
+                path,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "This label name has already been used",
+            ));
+        }
+        match state.labels.iter_mut().find(|label| label.id == id) {
+            Some(label) => {
+                label.name = name.to_string();
+                Ok(())
+            }
+            None => Err(error(
+                Method::PUT,
+                path,
+                StatusCode::NOT_FOUND,
+                "Label not found",
+            )),
+        }
+    }
+
+    async fn delete_label(&self, id: &str) -> Result {
+        let mut state = self.state();
+        if !state.has_label(id) {
+            return Err(error(
+                Method::DELETE,
+                format!("api/v1/labels/{}", id),
+                StatusCode::NOT_FOUND,
+                "Label not found",
+            ));
+        }
+        state.labels.retain(|label| label.id != id);
+        for device in &mut state.devices {
+            device.labels.retain(|label| label != id);
+        }
+        Ok(())
+    }
+
+    async fn add_device_label(&self, device_id: String, device_label: &DeviceLabel) -> Result {
+        let path = format!("api/v1/devices/{}/labels", device_id);
+        let mut state = self.state();
+        if !state.has_label(device_label.get_uuid()) {
+            return Err(error(
+                Method::POST,
+                path,
+                StatusCode::NOT_FOUND,
+                "Label not found",
+            ));
+        }
+        let device = state.device_mut(Method::POST, &device_id)?;
+        if !device.labels.contains(device_label.get_uuid()) {
+            device.labels.push(device_label.get_uuid().clone());
+        }
+        Ok(())
+    }
+
+    async fn remove_device_label(&self, device_id: String, device_label: &DeviceLabel) -> Result {
+        let path = format!(
+            "api/v1/devices/{}/labels/{}",
+            device_id,
+            device_label.get_uuid()
+        );
+        let mut state = self.state();
+        let device = state.device_mut(Method::DELETE, &device_id)?;
+        if !device.labels.contains(device_label.get_uuid()) {
+            return Err(error(
+                Method::DELETE,
+                path,
+                StatusCode::NOT_FOUND,
+                "Device label not found",
+            ));
+        }
+        device
+            .labels
+            .retain(|label| label != device_label.get_uuid());
+        Ok(())
+    }
+
+    async fn find_labels(&mut self, label: &str) -> Result<Vec<Label>> {
+        Ok(self.label_match.select(label, self.labels()))
+    }
+
+    async fn get_label_uuid(&mut self, label: &str, create_missing: bool) -> Result<String> {
+        if let Some(found) = self.label_match.select_one(label, self.labels())? {
+            return Ok(found.id);
+        }
+        if !create_missing || validate_uuid_input(label).is_ok() {
+            return Err(ConsoleError::UnknownLabel(label.to_string()));
+        }
+        let name = self.label_match.normalize(label);
+        Ok(self.post_label(&NewLabel::from_string(&name)).await?.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn new_device(dev_eui: u8) -> NewDevice {
+        NewDevice::new(
+            AppEui::from_bytes([1; 8]),
+            AppKey::from_bytes([2; 16]),
+            DevEui::from_bytes([dev_eui; 8]),
+            format!("device-{}", dev_eui),
+        )
+    }
+
+    fn label(id: &str) -> DeviceLabel {
+        DeviceLabel::from_uuid(id.to_string()).unwrap()
+    }
+
+    #[test]
+    fn duplicate_creates_conflict() {
+        let console = InMemoryConsole::new();
+        block_on(console.post_device(&new_device(3))).unwrap();
+        let duplicate = block_on(console.post_device(&new_device(3)));
+        assert!(
+            matches!(duplicate, Err(ConsoleError::Conflict(ref e)) if e.status == StatusCode::UNPROCESSABLE_ENTITY)
+        );
+        // the same DevEUI under another AppEUI is a different device
+        let mut other = new_device(3);
+        other.app_eui = AppEui::from_bytes([9; 8]);
+        block_on(console.post_device(&other)).unwrap();
+        assert_eq!(console.devices().len(), 2);
+
+        block_on(console.post_label(&NewLabel::from_string("warehouse"))).unwrap();
+        let duplicate = block_on(console.post_label(&NewLabel::from_string("warehouse")));
+        assert!(matches!(duplicate, Err(ConsoleError::Conflict(_))));
+        assert_eq!(console.labels().len(), 1);
+    }
+
+    #[test]
+    fn unknown_ids_are_not_found() {
+        let mut console = InMemoryConsole::new();
+        let unknown = new_id();
+        let not_found = |result: Result| matches!(result, Err(ConsoleError::NotFound(_)));
+
+        assert!(matches!(
+            block_on(console.get_device_by_id(&unknown)),
+            Err(ConsoleError::NotFound(_))
+        ));
+        assert!(not_found(block_on(console.delete_device(&unknown))));
+        assert!(not_found(block_on(
+            console.update_device(&unknown, &DeviceUpdate::new())
+        )));
+        assert!(not_found(block_on(console.delete_label(&unknown))));
+        assert!(not_found(block_on(console.rename_label(&unknown, "name"))));
+        assert!(matches!(
+            block_on(console.get_device(&(&new_device(3)).into())),
+            Err(ConsoleError::NoMatchingDevice(_))
+        ));
+        assert!(matches!(
+            block_on(console.get_label_uuid("missing", false)),
+            Err(ConsoleError::UnknownLabel(_))
+        ));
+    }
+
+    #[test]
+    fn attaches_and_detaches_labels() {
+        let console = InMemoryConsole::new();
+        let device = block_on(console.post_device(&new_device(3))).unwrap();
+        let warehouse = block_on(console.post_label(&NewLabel::from_string("warehouse"))).unwrap();
+        let attach =
+            || block_on(console.add_device_label(device.id().clone(), &label(&warehouse.id)));
+
+        // attaching twice keeps one label
+        attach().unwrap();
+        attach().unwrap();
+        assert_eq!(console.devices()[0].labels(), &vec![warehouse.id.clone()]);
+
+        let unknown_label =
+            block_on(console.add_device_label(device.id().clone(), &label(&new_id())));
+        assert!(matches!(unknown_label, Err(ConsoleError::NotFound(_))));
+        let unknown_device = block_on(console.add_device_label(new_id(), &label(&warehouse.id)));
+        assert!(matches!(unknown_device, Err(ConsoleError::NotFound(_))));
+
+        block_on(console.remove_device_label(device.id().clone(), &label(&warehouse.id))).unwrap();
+        assert!(console.devices()[0].labels().is_empty());
+        let detached =
+            block_on(console.remove_device_label(device.id().clone(), &label(&warehouse.id)));
+        assert!(matches!(detached, Err(ConsoleError::NotFound(_))));
+
+        // deleting a label detaches it from every device
+        attach().unwrap();
+        block_on(console.delete_label(&warehouse.id)).unwrap();
+        assert!(console.devices()[0].labels().is_empty());
+    }
+}
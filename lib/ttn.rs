@@ -1,12 +1,11 @@
-use super::NewDevice;
-use super::Result;
+use super::{AppEui, AppKey, DevEui, DryRun, NewDevice, Secret};
 use oauth2::{
     basic::BasicClient,
     prelude::{NewType, SecretNewType},
     AccessToken, AuthUrl, AuthorizationCode, ClientId, ClientSecret, RequestTokenError,
     TokenResponse, TokenUrl,
 };
-use reqwest::Client as ReqwestClient;
+use reqwest::{Client as ReqwestClient, Method};
 use serde_derive::{Deserialize, Serialize};
 use std::time::Duration;
 use url::Url;
@@ -24,10 +23,19 @@ const APP_BASE_URL: [&str; 4] = [
 
 const DEFAULT_TIMEOUT: u64 = 120;
 
+/// TTN restricts a token to at most this many application scopes
+pub const MAX_APPS_PER_TOKEN: usize = 10;
+
 const NULL_JSON: &str = "{}";
 
+pub type Result<T = ()> = std::result::Result<T, Box<dyn stdError>>;
+
 pub struct Client {
     client: ReqwestClient,
+    account_url: String,
+    handler_urls: Vec<String>,
+    // when set, device deletes are recorded instead of sent
+    dry_run: Option<DryRun>,
 }
 
 impl Client {
@@ -36,18 +44,44 @@ impl Client {
             client: ReqwestClient::builder()
                 .timeout(Duration::from_secs(DEFAULT_TIMEOUT))
                 .build()?,
+            account_url: ACCOUNT_BASE_URL.to_string(),
+            handler_urls: APP_BASE_URL.iter().map(|url| url.to_string()).collect(),
+            dry_run: None,
         })
     }
 
+    /// Account server to exchange access codes and tokens with, in place
+    /// of `ACCOUNT_BASE_URL`
+    pub fn with_account_url(mut self, url: &str) -> Client {
+        self.account_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Handlers to look applications up on, in place of `APP_BASE_URL`
+    pub fn with_handler_urls(mut self, urls: &[String]) -> Client {
+        self.handler_urls = urls
+            .iter()
+            .map(|url| url.trim_end_matches('/').to_string())
+            .collect();
+        self
+    }
+
+    /// Records device deletes in `dry_run` instead of sending them.
+    /// Token exchanges and reads still go to TTN.
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Client {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
     pub fn get_account_token(&self, access_code: AuthorizationCode) -> Result<AccessToken> {
         let client = BasicClient::new(
             ClientId::new("ttnctl".to_string()),
             Some(ClientSecret::new("ttnctl".to_string())),
             AuthUrl::new(Url::parse(
-                format!("{}/users/authorize", ACCOUNT_BASE_URL).as_str(),
+                format!("{}/users/authorize", self.account_url).as_str(),
             )?),
             Some(TokenUrl::new(Url::parse(
-                format!("{}/users/token", ACCOUNT_BASE_URL).as_str(),
+                format!("{}/users/token", self.account_url).as_str(),
             )?)),
         );
 
@@ -62,13 +96,13 @@ impl Client {
 
     fn get_with_token(&self, token: &str, path: &str) -> reqwest::RequestBuilder {
         self.client
-            .get(format!("{}{}", ACCOUNT_BASE_URL, path).as_str())
+            .get(format!("{}{}", self.account_url, path).as_str())
             .bearer_auth(token)
     }
 
     fn post_with_token(&self, token: &str, path: &str) -> reqwest::RequestBuilder {
         self.client
-            .post(format!("{}{}", ACCOUNT_BASE_URL, path).as_str())
+            .post(format!("{}{}", self.account_url, path).as_str())
             .bearer_auth(token)
     }
 
@@ -80,11 +114,17 @@ impl Client {
         Ok(apps)
     }
 
+    /// Restricted token for at most `MAX_APPS_PER_TOKEN` applications.
+    /// Fails with `Error::TokenConsumed` once the account token has been
+    /// exchanged as often as TTN allows; a new access code is needed then.
     pub async fn exchange_for_app_token(
         &mut self,
-        token: AccessToken,
+        token: &AccessToken,
         app_ids: Vec<String>,
     ) -> Result<String> {
+        if app_ids.len() > MAX_APPS_PER_TOKEN {
+            return Err(Error::TooManyApps.into());
+        }
         let mut token_request = RequestToken { scope: Vec::new() };
 
         for id in app_ids {
@@ -99,6 +139,8 @@ impl Client {
             let body = response.text().await.unwrap();
             let token_response: RequestTokenResponse = serde_json::from_str(&body)?;
             Ok(token_response.access_token)
+        } else if response.status() == 401 || response.status() == 403 {
+            Err(Error::TokenConsumed.into())
         } else {
             let body = response.text().await.unwrap();
             Err(body.into())
@@ -107,7 +149,7 @@ impl Client {
 
     pub async fn get_devices(&self, app: &str, token: &str) -> Result<Vec<TtnDevice>> {
         // We brute force going through handler URLs
-        for url in &APP_BASE_URL {
+        for url in &self.handler_urls {
             let request = self
                 .client
                 .get(format!("{}/applications/{}/devices", url, app).as_str())
@@ -134,18 +176,16 @@ impl Client {
 
     // DELETE /applications/{app_id}/devices/{dev_id}
     pub async fn delete_device(&self, device: TtnDevice, token: &str) -> Result<()> {
-        let request = self
-            .client
-            .delete(
-                format!(
-                    "{}/applications/{}/devices/{}",
-                    device.endpoint, device.app_id, device.dev_id
-                )
-                .as_str(),
-            )
-            .bearer_auth(token);
+        let url = format!(
+            "{}/applications/{}/devices/{}",
+            device.endpoint, device.app_id, device.dev_id
+        );
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.record(Method::DELETE, url, None);
+            return Ok(());
+        }
+        let request = self.client.delete(url.as_str()).bearer_auth(token);
         let response = request.send().await?;
-        println!("response {:?}", response);
         if response.status() == 200 {
             Ok(())
         } else {
@@ -196,54 +236,57 @@ pub struct TtnDevice {
     app_id: String,
     dev_id: String,
     lorawan_device: Device,
-    endpoint: &'static str,
+    endpoint: String,
 }
 
 impl TtnDevice {
-    fn from_raw(raw: TtnDeviceRaw, endpoint: &'static str) -> TtnDevice {
+    fn from_raw(raw: TtnDeviceRaw, endpoint: &str) -> TtnDevice {
         TtnDevice {
             app_id: raw.app_id,
             dev_id: raw.dev_id,
             lorawan_device: raw.lorawan_device,
-            endpoint,
+            endpoint: endpoint.to_string(),
         }
     }
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Device {
-    app_eui: String,
-    dev_eui: String,
+    app_eui: AppEui,
+    dev_eui: DevEui,
     app_id: String,
     dev_id: String,
     dev_addr: String,
-    nwk_s_key: String,
-    app_s_key: String,
-    app_key: String,
+    nwk_s_key: Secret<String>,
+    app_s_key: Secret<String>,
+    // ABP devices have no AppKey and TTN sends an empty string
+    #[serde(with = "empty_as_none")]
+    app_key: Option<Secret<AppKey>>,
     uses32_bit_f_cnt: bool,
     activation_constraints: String,
 }
 
 impl TtnDevice {
     pub fn derive_new_device_request(&self) -> Result<NewDevice> {
-        NewDevice::from_user_input(
-            self.lorawan_device.app_eui.clone(),
-            self.lorawan_device.app_key.clone(),
-            self.lorawan_device.dev_eui.clone(),
+        let app_key = self.lorawan_device.app_key.ok_or(Error::NoAppKey)?;
+        Ok(NewDevice::new(
+            self.lorawan_device.app_eui,
+            *app_key.expose_secret(),
+            self.lorawan_device.dev_eui,
             // assign it some unique'ish name
             self.lorawan_device.dev_id.clone(),
-        )
+        ))
     }
 
     pub fn appid(&self) -> &String {
         &self.app_id
     }
 
-    pub fn appkey(&self) -> &String {
-        &self.lorawan_device.app_key
+    pub fn appkey(&self) -> Option<&Secret<AppKey>> {
+        self.lorawan_device.app_key.as_ref()
     }
 
-    pub fn appskey(&self) -> &String {
+    pub fn appskey(&self) -> &Secret<String> {
         &self.lorawan_device.app_s_key
     }
 
@@ -255,6 +298,41 @@ impl TtnDevice {
     }
 }
 
+/// TTN encodes a missing key as an empty string
+mod empty_as_none {
+    use super::Secret;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::{fmt::Display, str::FromStr};
+
+    pub fn serialize<S, T>(value: &Option<Secret<T>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        match value {
+            Some(value) => value.serialize(serializer),
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<Secret<T>>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: FromStr,
+        T::Err: Display,
+    {
+        let input = String::deserialize(deserializer)?;
+        if input.is_empty() {
+            Ok(None)
+        } else {
+            input
+                .parse()
+                .map(|value| Some(Secret::new(value)))
+                .map_err(serde::de::Error::custom)
+        }
+    }
+}
+
 use std::error::Error as stdError;
 use std::{fmt, str};
 
@@ -263,6 +341,9 @@ pub enum Error {
     NoHandler,
     DeviceNotFound,
     CodeNotFound,
+    NoAppKey,
+    TokenConsumed,
+    TooManyApps,
 }
 
 impl fmt::Display for Error {
@@ -271,6 +352,16 @@ impl fmt::Display for Error {
             Error::NoHandler => write!(f, "No handler servers are associated with App"),
             Error::DeviceNotFound => write!(f, "Device not found for delete"),
             Error::CodeNotFound => write!(f, "Authorization code not found on TTN server"),
+            Error::NoAppKey => write!(f, "Device has no AppKey (ABP devices are not supported)"),
+            Error::TokenConsumed => write!(
+                f,
+                "Account token was already used up; a new access code is needed"
+            ),
+            Error::TooManyApps => write!(
+                f,
+                "A token can cover at most {} applications",
+                MAX_APPS_PER_TOKEN
+            ),
         }
     }
 }
@@ -281,6 +372,11 @@ impl stdError for Error {
             Error::NoHandler => "No handler servers are associated with App",
             Error::DeviceNotFound => "Device not found for delete",
             Error::CodeNotFound => "Authorization code not found on TTN server",
+            Error::NoAppKey => "Device has no AppKey (ABP devices are not supported)",
+            Error::TokenConsumed => {
+                "Account token was already used up; a new access code is needed"
+            }
+            Error::TooManyApps => "Too many applications for one token",
         }
     }
 
@@ -0,0 +1,187 @@
+//! The Things Stack (TTN v3) HTTP API: applications and end devices from
+//! the Identity Server, and their root keys from the Join Server.
+
+use super::{
+    source_api::{Paging, SourceClient, StatusError},
+    AppEui, AppKey, DevEui, NewDevice, Secret,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{error::Error as stdError, fmt};
+
+/// The Things Network's public European cluster
+pub const DEFAULT_CLUSTER_URL: &str = "https://eu1.cloud.thethings.network";
+
+pub use super::source_api::Result;
+
+pub struct Client {
+    api: SourceClient,
+}
+
+impl Client {
+    /// `cluster_url` is e.g. `https://eu1.cloud.thethings.network`, and
+    /// `api_key` a TTS API key that may list applications and devices and
+    /// read device keys
+    pub fn new(cluster_url: &str, api_key: &str) -> Result<Client> {
+        Ok(Client {
+            api: SourceClient::new(cluster_url, "authorization", api_key)?,
+        })
+    }
+
+    /// Applications the API key has access to
+    pub async fn get_applications(&self) -> Result<Vec<Application>> {
+        self.api
+            .get_all(
+                "api/v3/applications?field_mask=name",
+                Paging::Page,
+                |page: Page<Application>| page.applications,
+            )
+            .await
+    }
+
+    /// End devices registered in an application, without their keys
+    pub async fn get_end_devices(&self, app_id: &str) -> Result<Vec<EndDevice>> {
+        self.api
+            .get_all(
+                &format!("api/v3/applications/{}/devices?field_mask=name", app_id),
+                Paging::Page,
+                |page: Page<EndDevice>| page.end_devices,
+            )
+            .await
+    }
+
+    /// Reads the device's root keys from the Join Server. ABP devices and
+    /// devices joined through another Join Server have none.
+    pub async fn get_root_keys(&self, device: &EndDevice) -> Result<EndDevice> {
+        let path = format!(
+            "api/v3/js/applications/{}/devices/{}?field_mask=root_keys.app_key",
+            device.ids.application_ids.application_id, device.ids.device_id
+        );
+        let root_keys = match self.api.get::<EndDevice>(&path).await {
+            Ok(keyed) => keyed.root_keys,
+            Err(err) if StatusError::is_not_found(err.as_ref()) => None,
+            Err(err) => return Err(err),
+        };
+        Ok(EndDevice {
+            root_keys,
+            ..device.clone()
+        })
+    }
+}
+
+// One page of a listing; only the field of the listed type is present
+#[derive(Deserialize)]
+struct Page<T> {
+    #[serde(default = "Vec::new")]
+    applications: Vec<T>,
+    #[serde(default = "Vec::new")]
+    end_devices: Vec<T>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct Application {
+    ids: ApplicationIds,
+    #[serde(default)]
+    name: String,
+}
+
+impl Application {
+    pub fn id(&self) -> &String {
+        &self.ids.application_id
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+struct ApplicationIds {
+    application_id: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+struct EndDeviceIds {
+    device_id: String,
+    application_ids: ApplicationIds,
+    // absent for ABP devices
+    #[serde(default)]
+    dev_eui: Option<DevEui>,
+    #[serde(default)]
+    join_eui: Option<AppEui>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+struct RootKeys {
+    #[serde(default)]
+    app_key: Option<KeyEnvelope>,
+}
+
+// `key` is missing when the Join Server only has the key wrapped with a KEK
+#[derive(Clone, Deserialize, Serialize, Debug)]
+struct KeyEnvelope {
+    #[serde(default)]
+    key: Option<Secret<AppKey>>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct EndDevice {
+    ids: EndDeviceIds,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    root_keys: Option<RootKeys>,
+}
+
+impl EndDevice {
+    /// Requires the root keys, see `Client::get_root_keys`. The device
+    /// keeps its TTS name, or its device ID when it has none.
+    pub fn derive_new_device_request(&self) -> Result<NewDevice> {
+        let dev_eui = self.ids.dev_eui.ok_or(Error::NoDevEui)?;
+        let join_eui = self.ids.join_eui.ok_or(Error::NoDevEui)?;
+        let app_key = self
+            .root_keys
+            .as_ref()
+            .and_then(|keys| keys.app_key.as_ref())
+            .and_then(|envelope| envelope.key)
+            .ok_or(Error::NoAppKey)?;
+        let name = if self.name.is_empty() {
+            self.ids.device_id.clone()
+        } else {
+            self.name.clone()
+        };
+        Ok(NewDevice::new(
+            join_eui,
+            *app_key.expose_secret(),
+            dev_eui,
+            name,
+        ))
+    }
+
+    pub fn app_id(&self) -> &String {
+        &self.ids.application_ids.application_id
+    }
+
+    pub fn device_id(&self) -> &String {
+        &self.ids.device_id
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    NoDevEui,
+    NoAppKey,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoDevEui => write!(
+                f,
+                "Device has no DevEUI or JoinEUI (ABP devices are not supported)"
+            ),
+            Error::NoAppKey => write!(f, "Join Server has no readable AppKey for the device"),
+        }
+    }
+}
+
+impl stdError for Error {}
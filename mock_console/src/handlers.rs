@@ -0,0 +1,604 @@
+use super::{
+    ChirpstackDevice, Fault, State, TtnDevice, TtsDevice, CHIRPSTACK_API_TOKEN, TTS_API_KEY,
+};
+use helium_console::{ConsoleApi, ConsoleError, DeviceLabel, DeviceUpdate, GetDevice};
+use hyper::Body;
+use reset_router::{Data, Request, RequestExtensions, Response};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+type HandlerResult = Result<Response, Response>;
+
+fn respond(status: u16, body: String) -> Response {
+    http::Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.into())
+        .unwrap()
+}
+
+/// Responds with `value` as JSON; keys are written in full, like the real
+/// server does
+fn json<T: Serialize>(status: u16, value: &T) -> Response {
+    respond(status, serde_json::to_string(value).unwrap())
+}
+
+fn message(status: u16, message: &str) -> Response {
+    #[derive(Serialize)]
+    struct Message<'a> {
+        message: &'a str,
+    }
+    json(status, &Message { message })
+}
+
+fn console_error(error: ConsoleError) -> Response {
+    match (error.api_error(), &error) {
+        (Some(api_error), _) => message(api_error.status.as_u16(), &api_error.body),
+        (None, ConsoleError::NoMatchingDevice(_)) => message(404, "Device not found"),
+        (None, _) => message(400, &error.to_string()),
+    }
+}
+
+fn fault_response(fault: &Fault, status: u16) -> Response {
+    let mut builder = http::Response::builder().status(status);
+    if let Some(retry_after) = fault.retry_after {
+        builder = builder.header("retry-after", retry_after.to_string());
+    }
+    builder
+        .body(format!("{{\"message\":\"injected {}\"}}", status).into())
+        .unwrap()
+}
+
+fn bearer<'a>(parts: &'a http::request::Parts, header: &str) -> Option<&'a str> {
+    parts
+        .headers
+        .get(header)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Applies any queued fault and checks the Console API key
+async fn prepare(parts: &http::request::Parts) -> Result<Data<State>, Response> {
+    let state = apply_fault(parts).await?;
+    let key = parts.headers.get("key").and_then(|key| key.to_str().ok());
+    if key == Some(state.api_key.as_str()) {
+        Ok(state)
+    } else {
+        Err(message(401, "Unauthorized"))
+    }
+}
+
+/// Applies any queued fault and checks the bearer token in `header`
+async fn prepare_bearer(
+    parts: &http::request::Parts,
+    header: &str,
+    token: &str,
+) -> Result<Data<State>, Response> {
+    let state = apply_fault(parts).await?;
+    if bearer(parts, header) == Some(token) {
+        Ok(state)
+    } else {
+        Err(message(401, "Unauthorized"))
+    }
+}
+
+/// Applies any queued fault, for routes with their own credentials
+async fn apply_fault(parts: &http::request::Parts) -> Result<Data<State>, Response> {
+    let state = parts
+        .data::<State>()
+        .ok_or_else(|| message(500, "mock state missing"))?;
+
+    if let Some(fault) = state.take_fault(&parts.method, parts.uri.path()) {
+        if let Some(delay_ms) = fault.delay_ms {
+            tokio::time::delay_for(Duration::from_millis(delay_ms)).await;
+        }
+        if let Some(status) = fault.status {
+            return Err(fault_response(&fault, status));
+        }
+    }
+    Ok(state)
+}
+
+async fn read_json<T: DeserializeOwned>(body: Body) -> Result<T, Response> {
+    let bytes = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| message(400, &e.to_string()))?;
+    serde_json::from_slice(&bytes).map_err(|e| message(422, &e.to_string()))
+}
+
+fn query(parts: &http::request::Parts) -> HashMap<String, String> {
+    url::form_urlencoded::parse(parts.uri.query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn capture(parts: &http::request::Parts, index: usize) -> String {
+    parts
+        .captures()
+        .and_then(|captures| captures.get(index))
+        .map(|capture| capture.to_string())
+        .unwrap_or_default()
+}
+
+/// The page of `records` asked for with `limit` and either a 1-based
+/// `page`, as TTS pages, or an `offset`, as ChirpStack does. Without a
+/// `limit` every record is returned.
+fn page_of<T>(query: &HashMap<String, String>, records: Vec<T>) -> Vec<T> {
+    let limit: usize = match query.get("limit").and_then(|limit| limit.parse().ok()) {
+        Some(limit) if limit > 0 => limit,
+        _ => return records,
+    };
+    let skip = match query
+        .get("page")
+        .and_then(|page| page.parse::<usize>().ok())
+    {
+        Some(page) => page.saturating_sub(1) * limit,
+        None => query
+            .get("offset")
+            .and_then(|offset| offset.parse().ok())
+            .unwrap_or(0),
+    };
+    records.into_iter().skip(skip).take(limit).collect()
+}
+
+/// Answers a listing with cursor pagination when `page_size` is given
+/// and with a bare array otherwise
+fn paginated<T: Serialize>(query: &HashMap<String, String>, records: Vec<T>) -> Response {
+    #[derive(Serialize)]
+    struct Page<'a, T> {
+        data: &'a [T],
+        next_cursor: Option<String>,
+    }
+
+    let page_size: usize = match query.get("page_size").and_then(|size| size.parse().ok()) {
+        Some(page_size) => page_size,
+        None => return json(200, &records),
+    };
+    let start = query
+        .get("cursor")
+        .and_then(|cursor| cursor.parse().ok())
+        .unwrap_or(0usize)
+        .min(records.len());
+    let end = (start + page_size).min(records.len());
+    let next_cursor = if end < records.len() {
+        Some(end.to_string())
+    } else {
+        None
+    };
+    json(
+        200,
+        &Page {
+            data: &records[start..end],
+            next_cursor,
+        },
+    )
+}
+
+pub async fn get_organization(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    match state.console().get_organization().await {
+        Ok(organization) => Ok(json(200, &organization)),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+pub async fn get_devices(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    let query = query(&parts);
+
+    if let (Some(dev_eui), Some(app_eui), Some(app_key)) = (
+        query.get("dev_eui"),
+        query.get("app_eui"),
+        query.get("app_key"),
+    ) {
+        let request = GetDevice::from_user_input(app_eui.clone(), app_key.clone(), dev_eui.clone())
+            .map_err(|e| message(422, &e.to_string()))?;
+        return match state.console().get_device(&request).await {
+            Ok(device) => Ok(json(200, &device)),
+            Err(e) => Err(console_error(e)),
+        };
+    }
+
+    Ok(paginated(&query, state.console().devices()))
+}
+
+pub async fn post_device(req: Request) -> HandlerResult {
+    let (parts, body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    let request = read_json(body).await?;
+    match state.console().post_device(&request).await {
+        Ok(device) => Ok(json(201, &device)),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+pub async fn get_device_by_id(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    match state.console().get_device_by_id(&capture(&parts, 1)).await {
+        Ok(device) => Ok(json(200, &device)),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+pub async fn update_device(req: Request) -> HandlerResult {
+    let (parts, body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    let update: DeviceUpdate = read_json(body).await?;
+    let id = capture(&parts, 1);
+    match state.console().update_device(&id, &update).await {
+        Ok(()) => Ok(message(200, "Device updated")),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+pub async fn delete_device(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    match state.console().delete_device(&capture(&parts, 1)).await {
+        Ok(()) => Ok(message(200, "Device deleted")),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+pub async fn add_device_label(req: Request) -> HandlerResult {
+    let (parts, body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    let device_label: DeviceLabel = read_json(body).await?;
+    match state
+        .console()
+        .add_device_label(capture(&parts, 1), &device_label)
+        .await
+    {
+        Ok(()) => Ok(message(200, "Device added to label successfully")),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+pub async fn remove_device_label(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    let device_label =
+        DeviceLabel::from_uuid(capture(&parts, 2)).map_err(|e| message(422, &e.to_string()))?;
+    match state
+        .console()
+        .remove_device_label(capture(&parts, 1), &device_label)
+        .await
+    {
+        Ok(()) => Ok(message(200, "Device removed from label successfully")),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+pub async fn get_labels(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    Ok(paginated(&query(&parts), state.console().labels()))
+}
+
+pub async fn post_label(req: Request) -> HandlerResult {
+    let (parts, body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    let request = read_json(body).await?;
+    match state.console().post_label(&request).await {
+        Ok(label) => Ok(json(201, &label)),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+pub async fn rename_label(req: Request) -> HandlerResult {
+    #[derive(serde_derive::Deserialize)]
+    struct Rename {
+        name: String,
+    }
+
+    let (parts, body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    let rename: Rename = read_json(body).await?;
+    match state
+        .console()
+        .rename_label(&capture(&parts, 1), &rename.name)
+        .await
+    {
+        Ok(()) => Ok(message(200, "Label updated")),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+pub async fn delete_label(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare(&parts).await?;
+    match state.console().delete_label(&capture(&parts, 1)).await {
+        Ok(()) => Ok(message(200, "Label deleted")),
+        Err(e) => Err(console_error(e)),
+    }
+}
+
+/// Fault injection is not behind the API key so that test harnesses
+/// can queue a 401
+pub async fn post_fault(req: Request) -> HandlerResult {
+    let (parts, body) = req.into_parts();
+    let state = parts
+        .data::<State>()
+        .ok_or_else(|| message(500, "mock state missing"))?;
+    let fault: Fault = read_json(body).await?;
+    state.inject(fault);
+    Ok(message(200, "Fault queued"))
+}
+
+pub async fn delete_faults(req: Request) -> HandlerResult {
+    let state = req
+        .data::<State>()
+        .ok_or_else(|| message(500, "mock state missing"))?;
+    state.clear_faults();
+    Ok(message(200, "Faults cleared"))
+}
+
+fn tts_ids(device: &TtsDevice) -> serde_json::Value {
+    serde_json::json!({
+        "device_id": device.device_id,
+        "application_ids": { "application_id": device.app_id },
+        "dev_eui": device.dev_eui,
+        "join_eui": device.join_eui,
+    })
+}
+
+async fn prepare_tts(parts: &http::request::Parts) -> Result<Data<State>, Response> {
+    prepare_bearer(parts, "authorization", TTS_API_KEY).await
+}
+
+pub async fn get_tts_applications(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare_tts(&parts).await?;
+    let mut ids: Vec<String> = state
+        .tts_devices()
+        .into_iter()
+        .map(|device| device.app_id)
+        .collect();
+    ids.sort();
+    ids.dedup();
+    let applications: Vec<serde_json::Value> = page_of(&query(&parts), ids)
+        .iter()
+        .map(|id| serde_json::json!({ "ids": { "application_id": id }, "name": id }))
+        .collect();
+    Ok(json(
+        200,
+        &serde_json::json!({ "applications": applications }),
+    ))
+}
+
+pub async fn get_tts_devices(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare_tts(&parts).await?;
+    let app_id = capture(&parts, 1);
+    let devices: Vec<TtsDevice> = state
+        .tts_devices()
+        .into_iter()
+        .filter(|device| device.app_id == app_id)
+        .collect();
+    let end_devices: Vec<serde_json::Value> = page_of(&query(&parts), devices)
+        .iter()
+        .map(|device| serde_json::json!({ "ids": tts_ids(device), "name": device.name }))
+        .collect();
+    Ok(json(
+        200,
+        &serde_json::json!({ "end_devices": end_devices }),
+    ))
+}
+
+pub async fn get_tts_device_keys(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare_tts(&parts).await?;
+    let (app_id, device_id) = (capture(&parts, 1), capture(&parts, 2));
+    let device = state
+        .tts_devices()
+        .into_iter()
+        .find(|device| device.app_id == app_id && device.device_id == device_id)
+        .ok_or_else(|| message(404, "end device not found"))?;
+    match &device.app_key {
+        Some(app_key) => Ok(json(
+            200,
+            &serde_json::json!({
+                "ids": tts_ids(&device),
+                "root_keys": { "app_key": { "key": app_key } },
+            }),
+        )),
+        None => Err(message(404, "end device not found")),
+    }
+}
+
+async fn prepare_chirpstack(parts: &http::request::Parts) -> Result<Data<State>, Response> {
+    prepare_bearer(parts, "grpc-metadata-authorization", CHIRPSTACK_API_TOKEN).await
+}
+
+/// ChirpStack listings are `{ "totalCount": "<n>", "result": [...] }`,
+/// where the count is of every record, not only those of the page
+fn chirpstack_page(parts: &http::request::Parts, records: Vec<serde_json::Value>) -> Response {
+    let total = records.len();
+    json(
+        200,
+        &serde_json::json!({
+            "totalCount": total.to_string(),
+            "result": page_of(&query(parts), records),
+        }),
+    )
+}
+
+pub async fn get_chirpstack_applications(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare_chirpstack(&parts).await?;
+    let mut applications: Vec<(String, String)> = state
+        .chirpstack_devices()
+        .into_iter()
+        .map(|device| (device.application_id, device.application_name))
+        .collect();
+    applications.sort();
+    applications.dedup();
+    Ok(chirpstack_page(
+        &parts,
+        applications
+            .into_iter()
+            .map(|(id, name)| serde_json::json!({ "id": id, "name": name }))
+            .collect(),
+    ))
+}
+
+pub async fn get_chirpstack_devices(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare_chirpstack(&parts).await?;
+    let application_id = query(&parts).get("applicationID").cloned();
+    Ok(chirpstack_page(
+        &parts,
+        state
+            .chirpstack_devices()
+            .iter()
+            .filter(|device| Some(&device.application_id) == application_id.as_ref())
+            .map(|device: &ChirpstackDevice| {
+                serde_json::json!({
+                    "devEUI": device.dev_eui,
+                    "name": device.name,
+                    "applicationID": device.application_id,
+                    "deviceProfileName": device.device_profile_name,
+                })
+            })
+            .collect(),
+    ))
+}
+
+pub async fn get_chirpstack_device_keys(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = prepare_chirpstack(&parts).await?;
+    let dev_eui = capture(&parts, 1);
+    let nwk_key = state
+        .chirpstack_devices()
+        .into_iter()
+        .find(|device| device.dev_eui.eq_ignore_ascii_case(&dev_eui))
+        .and_then(|device| device.nwk_key)
+        .ok_or_else(|| message(404, "object does not exist"))?;
+    Ok(json(
+        200,
+        &serde_json::json!({ "deviceKeys": { "nwkKey": nwk_key, "appKey": "" } }),
+    ))
+}
+
+/// The raw device record of a TTN handler, with the session keys zeroed
+fn ttn_device(device: &TtnDevice) -> serde_json::Value {
+    serde_json::json!({
+        "app_id": device.app_id,
+        "dev_id": device.dev_id,
+        "lorawan_device": {
+            "app_eui": device.app_eui,
+            "dev_eui": device.dev_eui,
+            "app_id": device.app_id,
+            "dev_id": device.dev_id,
+            "dev_addr": "",
+            "nwk_s_key": "",
+            "app_s_key": "",
+            "app_key": device.app_key.clone().unwrap_or_default(),
+            "uses32_bit_f_cnt": true,
+            "activation_constraints": "",
+        },
+    })
+}
+
+/// OAuth2 token endpoint, answering an authorization code grant
+pub async fn post_ttn_token(req: Request) -> HandlerResult {
+    let (parts, body) = req.into_parts();
+    let state = apply_fault(&parts).await?;
+    let bytes = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| message(400, &e.to_string()))?;
+    let code = url::form_urlencoded::parse(&bytes)
+        .find(|(name, _)| name == "code")
+        .map(|(_, code)| code.into_owned())
+        .unwrap_or_default();
+    match state.exchange_ttn_access_code(&code) {
+        Some(token) => Ok(json(
+            200,
+            &serde_json::json!({ "access_token": token, "token_type": "bearer" }),
+        )),
+        None => Err(json(400, &serde_json::json!({ "error": "invalid_grant" }))),
+    }
+}
+
+pub async fn post_ttn_restrict_token(req: Request) -> HandlerResult {
+    #[derive(Deserialize)]
+    struct RestrictToken {
+        scope: Vec<String>,
+    }
+
+    let (parts, body) = req.into_parts();
+    let state = apply_fault(&parts).await?;
+    let account_token = bearer(&parts, "authorization").unwrap_or_default();
+    let request: RestrictToken = read_json(body).await?;
+    let app_ids = request
+        .scope
+        .iter()
+        .map(|scope| scope.trim_start_matches("apps:").to_string())
+        .collect();
+    match state.restrict_ttn_token(account_token, app_ids) {
+        Some(token) => Ok(json(200, &serde_json::json!({ "access_token": token }))),
+        None => Err(message(401, "token already used")),
+    }
+}
+
+pub async fn get_ttn_applications(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let state = apply_fault(&parts).await?;
+    if !state.is_ttn_account_token(bearer(&parts, "authorization").unwrap_or_default()) {
+        return Err(message(401, "Unauthorized"));
+    }
+    let mut ids: Vec<String> = state
+        .ttn_devices()
+        .into_iter()
+        .map(|device| device.app_id)
+        .collect();
+    ids.sort();
+    ids.dedup();
+    let applications: Vec<serde_json::Value> = ids
+        .iter()
+        .map(|id| serde_json::json!({ "id": id, "name": id, "euis": [], "access_keys": [] }))
+        .collect();
+    Ok(json(200, &applications))
+}
+
+/// Checks that the bearer token was restricted to the captured application
+async fn ttn_handler(parts: &http::request::Parts) -> Result<(Data<State>, String), Response> {
+    let state = apply_fault(parts).await?;
+    let app_id = capture(parts, 1);
+    if state.ttn_token_covers(bearer(parts, "authorization").unwrap_or_default(), &app_id) {
+        Ok((state, app_id))
+    } else {
+        Err(message(401, "Unauthorized"))
+    }
+}
+
+pub async fn get_ttn_devices(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let (state, app_id) = ttn_handler(&parts).await?;
+    let devices: Vec<serde_json::Value> = state
+        .ttn_devices()
+        .iter()
+        .filter(|device| device.app_id == app_id)
+        .map(ttn_device)
+        .collect();
+    // handlers answer an application without devices with `{}`
+    if devices.is_empty() {
+        Ok(respond(200, "{}".to_string()))
+    } else {
+        Ok(json(200, &serde_json::json!({ "devices": devices })))
+    }
+}
+
+pub async fn delete_ttn_device(req: Request) -> HandlerResult {
+    let (parts, _body) = req.into_parts();
+    let (state, app_id) = ttn_handler(&parts).await?;
+    if state.delete_ttn_device(&app_id, &capture(&parts, 2)) {
+        Ok(respond(200, "{}".to_string()))
+    } else {
+        Err(message(404, "device not found"))
+    }
+}
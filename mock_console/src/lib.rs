@@ -0,0 +1,458 @@
+//! A local stand-in for the Helium Console API.
+//!
+//! Devices and labels live in a `helium_console::testing::InMemoryConsole`,
+//! so the mock answers with the same 404s and 422s as the real server.
+//! Faults (error statuses, rate limiting, slow responses) can be queued
+//! with `MockConsole::inject` or by POSTing a `Fault` to `/_mock/faults`.
+//!
+//! The same server also stands in for a The Things Stack cluster under
+//! `/api/v3`, serving the `TtsDevice`s added with `State::add_tts_device`.
+//! Those routes take `TTS_API_KEY` as a bearer token, like TTS does.
+//! Likewise `/api/applications` and `/api/devices` stand in for a
+//! ChirpStack server, serving the `ChirpstackDevice`s added with
+//! `State::add_chirpstack_device` to holders of `CHIRPSTACK_API_TOKEN`.
+//! Both page their listings like the real servers do.
+//!
+//! Under `/ttn` the server stands in for the TTN (v2) account server, and
+//! under `/ttn/handler` for one of its handlers, serving the `TtnDevice`s
+//! added with `State::add_ttn_device`. Like ttnctl, those routes take no
+//! API key: an access code added with `State::add_ttn_access_code` is
+//! exchanged once for an account token, which can be restricted once to
+//! an application token.
+
+use futures::channel::oneshot;
+use helium_console::testing::InMemoryConsole;
+use reset_router::{Data, Response, Router};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+mod handlers;
+
+/// The API key the TTS stand-in accepts
+pub const TTS_API_KEY: &str = "NNSXS.MOCK-TTS-API-KEY";
+/// The API token the ChirpStack stand-in accepts
+pub const CHIRPSTACK_API_TOKEN: &str = "mock-chirpstack-api-token";
+
+pub type Result<T = ()> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// A canned response for the next requests matching `method` and `path`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Fault {
+    /// Status to answer with instead of handling the request.
+    /// Without one, the request is handled normally after `delay_ms`.
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// Wait this long before answering
+    #[serde(default)]
+    pub delay_ms: Option<u64>,
+    /// Value of the Retry-After header, in seconds
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+    /// Only match requests with this method
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Only match requests whose path starts with this
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Number of matching requests the fault applies to
+    #[serde(default = "default_times")]
+    pub times: usize,
+}
+
+fn default_times() -> usize {
+    1
+}
+
+impl Fault {
+    fn new(status: Option<u16>, delay_ms: Option<u64>) -> Fault {
+        Fault {
+            status,
+            delay_ms,
+            retry_after: None,
+            method: None,
+            path: None,
+            times: default_times(),
+        }
+    }
+
+    /// Answer with `status` instead of handling the request
+    pub fn status(status: u16) -> Fault {
+        Fault::new(Some(status), None)
+    }
+
+    /// Handle the request normally, but only after `delay_ms`
+    pub fn delay(delay_ms: u64) -> Fault {
+        Fault::new(None, Some(delay_ms))
+    }
+
+    pub fn retry_after(mut self, seconds: u64) -> Fault {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn on(mut self, method: &str, path: &str) -> Fault {
+        self.method = Some(method.to_string());
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn times(mut self, times: usize) -> Fault {
+        self.times = times;
+        self
+    }
+
+    fn matches(&self, method: &http::Method, path: &str) -> bool {
+        let method_matches = match &self.method {
+            Some(expected) => expected.eq_ignore_ascii_case(method.as_str()),
+            None => true,
+        };
+        let path_matches = match &self.path {
+            Some(prefix) => path
+                .trim_start_matches('/')
+                .starts_with(prefix.trim_start_matches('/')),
+            None => true,
+        };
+        method_matches && path_matches
+    }
+}
+
+/// An end device registered in the TTS stand-in
+#[derive(Clone, Debug)]
+pub struct TtsDevice {
+    pub app_id: String,
+    pub device_id: String,
+    pub name: String,
+    /// Absent for ABP devices
+    pub dev_eui: Option<String>,
+    pub join_eui: Option<String>,
+    /// Absent when the Join Server has no key for the device
+    pub app_key: Option<String>,
+}
+
+/// A device registered in the ChirpStack stand-in
+#[derive(Clone, Debug)]
+pub struct ChirpstackDevice {
+    pub application_id: String,
+    pub application_name: String,
+    pub dev_eui: String,
+    pub name: String,
+    pub device_profile_name: String,
+    /// Absent for ABP devices
+    pub nwk_key: Option<String>,
+}
+
+/// An end device registered in the TTN (v2) stand-in
+#[derive(Clone, Debug)]
+pub struct TtnDevice {
+    pub app_id: String,
+    pub dev_id: String,
+    pub dev_eui: String,
+    pub app_eui: String,
+    /// Absent for ABP devices
+    pub app_key: Option<String>,
+}
+
+/// Credentials handed out by the TTN stand-in
+#[derive(Debug, Default)]
+struct TtnTokens {
+    /// Access codes not exchanged yet
+    access_codes: Vec<String>,
+    /// Account tokens, and whether each was restricted already
+    account_tokens: HashMap<String, bool>,
+    /// Application tokens, and the applications each covers
+    app_tokens: HashMap<String, Vec<String>>,
+}
+
+/// Shared state behind every handler
+pub struct State {
+    api_key: String,
+    console: InMemoryConsole,
+    faults: Mutex<Vec<Fault>>,
+    tts_devices: Mutex<Vec<TtsDevice>>,
+    chirpstack_devices: Mutex<Vec<ChirpstackDevice>>,
+    ttn_devices: Mutex<Vec<TtnDevice>>,
+    ttn_tokens: Mutex<TtnTokens>,
+}
+
+impl State {
+    pub fn new(api_key: String) -> State {
+        State {
+            api_key,
+            console: InMemoryConsole::new(),
+            faults: Mutex::new(Vec::new()),
+            tts_devices: Mutex::new(Vec::new()),
+            chirpstack_devices: Mutex::new(Vec::new()),
+            ttn_devices: Mutex::new(Vec::new()),
+            ttn_tokens: Mutex::new(TtnTokens::default()),
+        }
+    }
+
+    /// Registers a device, and its application, in the TTS stand-in
+    pub fn add_tts_device(&self, device: TtsDevice) {
+        self.tts_devices.lock().unwrap().push(device);
+    }
+
+    fn tts_devices(&self) -> Vec<TtsDevice> {
+        self.tts_devices.lock().unwrap().clone()
+    }
+
+    /// Registers a device, and its application, in the ChirpStack stand-in
+    pub fn add_chirpstack_device(&self, device: ChirpstackDevice) {
+        self.chirpstack_devices.lock().unwrap().push(device);
+    }
+
+    fn chirpstack_devices(&self) -> Vec<ChirpstackDevice> {
+        self.chirpstack_devices.lock().unwrap().clone()
+    }
+
+    /// Registers a device, and its application, in the TTN stand-in
+    pub fn add_ttn_device(&self, device: TtnDevice) {
+        self.ttn_devices.lock().unwrap().push(device);
+    }
+
+    /// Devices still registered in the TTN stand-in
+    pub fn ttn_devices(&self) -> Vec<TtnDevice> {
+        self.ttn_devices.lock().unwrap().clone()
+    }
+
+    fn delete_ttn_device(&self, app_id: &str, dev_id: &str) -> bool {
+        let mut devices = self.ttn_devices.lock().unwrap();
+        let count = devices.len();
+        devices.retain(|device| device.app_id != app_id || device.dev_id != dev_id);
+        devices.len() < count
+    }
+
+    /// Accepts `access_code` for one exchange against an account token
+    pub fn add_ttn_access_code(&self, access_code: &str) {
+        let mut tokens = self.ttn_tokens.lock().unwrap();
+        tokens.access_codes.push(access_code.to_string());
+    }
+
+    /// Uses up `access_code` for a new account token
+    fn exchange_ttn_access_code(&self, access_code: &str) -> Option<String> {
+        let mut tokens = self.ttn_tokens.lock().unwrap();
+        let index = tokens
+            .access_codes
+            .iter()
+            .position(|code| code == access_code)?;
+        tokens.access_codes.remove(index);
+        let token = format!("ttn-account-token-{}", tokens.account_tokens.len() + 1);
+        tokens.account_tokens.insert(token.clone(), false);
+        Some(token)
+    }
+
+    fn is_ttn_account_token(&self, token: &str) -> bool {
+        self.ttn_tokens
+            .lock()
+            .unwrap()
+            .account_tokens
+            .contains_key(token)
+    }
+
+    /// Uses up `account_token` for an application token covering `app_ids`
+    fn restrict_ttn_token(&self, account_token: &str, app_ids: Vec<String>) -> Option<String> {
+        let mut tokens = self.ttn_tokens.lock().unwrap();
+        match tokens.account_tokens.get_mut(account_token) {
+            Some(restricted) if !*restricted => *restricted = true,
+            _ => return None,
+        }
+        let token = format!("ttn-app-token-{}", tokens.app_tokens.len() + 1);
+        tokens.app_tokens.insert(token.clone(), app_ids);
+        Some(token)
+    }
+
+    fn ttn_token_covers(&self, app_token: &str, app_id: &str) -> bool {
+        self.ttn_tokens
+            .lock()
+            .unwrap()
+            .app_tokens
+            .get(app_token)
+            .map(|app_ids| app_ids.iter().any(|id| id == app_id))
+            .unwrap_or(false)
+    }
+
+    pub fn console(&self) -> &InMemoryConsole {
+        &self.console
+    }
+
+    pub fn inject(&self, fault: Fault) {
+        self.faults.lock().unwrap().push(fault);
+    }
+
+    pub fn clear_faults(&self) {
+        self.faults.lock().unwrap().clear();
+    }
+
+    /// Takes one use of the first fault matching the request
+    fn take_fault(&self, method: &http::Method, path: &str) -> Option<Fault> {
+        let mut faults = self.faults.lock().unwrap();
+        let index = faults
+            .iter()
+            .position(|fault| fault.matches(method, path))?;
+        let fault = faults[index].clone();
+        faults[index].times -= 1;
+        if faults[index].times == 0 {
+            faults.remove(index);
+        }
+        Some(fault)
+    }
+}
+
+pub fn router(state: Arc<State>) -> Result<Router> {
+    use handlers::*;
+    use http::Method;
+
+    Ok(Router::build()
+        .wrapped_data(Data::from_arc(state))
+        .add(Method::GET, r"^/api/v1/organization$", get_organization)
+        .add(Method::GET, r"^/api/v1/devices$", get_devices)
+        .add(Method::POST, r"^/api/v1/devices$", post_device)
+        .add(Method::GET, r"^/api/v1/devices/([^/]+)$", get_device_by_id)
+        .add(Method::PUT, r"^/api/v1/devices/([^/]+)$", update_device)
+        .add(Method::DELETE, r"^/api/v1/devices/([^/]+)$", delete_device)
+        .add(
+            Method::POST,
+            r"^/api/v1/devices/([^/]+)/labels$",
+            add_device_label,
+        )
+        .add(
+            Method::DELETE,
+            r"^/api/v1/devices/([^/]+)/labels/([^/]+)$",
+            remove_device_label,
+        )
+        .add(Method::GET, r"^/api/v1/labels$", get_labels)
+        .add(Method::POST, r"^/api/v1/labels$", post_label)
+        .add(Method::PUT, r"^/api/v1/labels/([^/]+)$", rename_label)
+        .add(Method::DELETE, r"^/api/v1/labels/([^/]+)$", delete_label)
+        .add(Method::GET, r"^/api/v3/applications$", get_tts_applications)
+        .add(
+            Method::GET,
+            r"^/api/v3/applications/([^/]+)/devices$",
+            get_tts_devices,
+        )
+        .add(
+            Method::GET,
+            r"^/api/v3/js/applications/([^/]+)/devices/([^/]+)$",
+            get_tts_device_keys,
+        )
+        .add(
+            Method::GET,
+            r"^/api/applications$",
+            get_chirpstack_applications,
+        )
+        .add(Method::GET, r"^/api/devices$", get_chirpstack_devices)
+        .add(
+            Method::GET,
+            r"^/api/devices/([^/]+)/keys$",
+            get_chirpstack_device_keys,
+        )
+        .add(Method::POST, r"^/ttn/users/token$", post_ttn_token)
+        .add(
+            Method::POST,
+            r"^/ttn/users/restrict-token$",
+            post_ttn_restrict_token,
+        )
+        .add(
+            Method::GET,
+            r"^/ttn/api/v2/applications$",
+            get_ttn_applications,
+        )
+        .add(
+            Method::GET,
+            r"^/ttn/handler/applications/([^/]+)/devices$",
+            get_ttn_devices,
+        )
+        .add(
+            Method::DELETE,
+            r"^/ttn/handler/applications/([^/]+)/devices/([^/]+)$",
+            delete_ttn_device,
+        )
+        .add(Method::POST, r"^/_mock/faults$", post_fault)
+        .add(Method::DELETE, r"^/_mock/faults$", delete_faults)
+        .add_not_found(|_| async {
+            Ok::<_, Response>(
+                http::Response::builder()
+                    .status(404)
+                    .body("404".into())
+                    .unwrap(),
+            )
+        })
+        .finish()?)
+}
+
+/// A mock server running on a background thread, stopped on drop
+pub struct MockConsole {
+    addr: SocketAddr,
+    state: Arc<State>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MockConsole {
+    /// Starts a server on an ephemeral localhost port that accepts `api_key`
+    pub fn start(api_key: &str) -> Result<MockConsole> {
+        let state = Arc::new(State::new(api_key.to_string()));
+        let router = router(state.clone())?;
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let (addr_tx, addr_rx) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            let mut runtime = tokio::runtime::Runtime::new().expect("tokio runtime");
+            runtime.block_on(async move {
+                let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+                let server = hyper::Server::bind(&addr).serve(router);
+                addr_tx.send(server.local_addr()).unwrap();
+                let _ = server
+                    .with_graceful_shutdown(async {
+                        let _ = shutdown_rx.await;
+                    })
+                    .await;
+            })
+        });
+
+        Ok(MockConsole {
+            addr: addr_rx.recv()?,
+            state,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Base URL of the TTN (v2) account server stand-in
+    pub fn ttn_account_url(&self) -> String {
+        format!("{}/ttn", self.url())
+    }
+
+    /// Base URL of the TTN (v2) handler stand-in
+    pub fn ttn_handler_url(&self) -> String {
+        format!("{}/ttn/handler", self.url())
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn inject(&self, fault: Fault) {
+        self.state.inject(fault)
+    }
+}
+
+impl Drop for MockConsole {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
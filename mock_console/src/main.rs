@@ -0,0 +1,28 @@
+use mock_console::{router, State};
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Get the port number to listen on.
+    let port = env::var("PORT")
+        .unwrap_or_else(|_| "3001".to_string())
+        .parse()
+        .expect("PORT must be a number");
+
+    // API key the mock accepts in the `key` header
+    let api_key = env::var("MOCK_CONSOLE_KEY").expect("MOCK_CONSOLE_KEY must be set");
+
+    let router = router(Arc::new(State::new(api_key)))?;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+    let server = hyper::Server::bind(&addr).serve(router);
+    println!("Mock Console listening on http://{}", addr);
+
+    server.await?;
+
+    Ok(())
+}
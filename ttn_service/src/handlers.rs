@@ -60,7 +60,7 @@ pub async fn exchange(req: Request) -> Result<Response, Response> {
             let account_token = AccessToken::new(request.account_token);
 
             let restricted_token = match ttn_client
-                .exchange_for_app_token(account_token, request.apps)
+                .exchange_for_app_token(&account_token, request.apps)
                 .await
             {
                 Ok(token) => token,